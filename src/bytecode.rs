@@ -0,0 +1,646 @@
+use super::parse::Parser;
+use super::program::{Program, SourceMap};
+use super::vm::Instruction;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+
+/// Primeros bytes de todo archivo `.vmb`.
+///
+/// Después vienen la versión (`u16`), las banderas (`u16`), la tabla de
+/// cadenas, la de constantes, las instrucciones y, si la bandera
+/// `FLAG_DEBUG` está encendida, la sección de depuración con el mapa de
+/// fuentes y las etiquetas. Los enteros se guardan en little-endian y los
+/// índices y operandos como `u32`.
+pub const MAGIC: &[u8; 4] = b"VMBC";
+pub const VERSION: u16 = 1;
+/// El archivo incluye el mapa de fuentes y las etiquetas.
+const FLAG_DEBUG: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BytecodeError {
+    /// El archivo no empieza con `MAGIC`.
+    BadMagic,
+    UnsupportedVersion(u16),
+    /// El archivo terminó antes de lo esperado.
+    Truncated,
+    InvalidOpcode(u8),
+    InvalidConstantTag(u8),
+    /// Un índice apunta fuera de la tabla de constantes o de cadenas.
+    InvalidIndex(u32),
+    /// Una constante no es del tipo que la instrucción necesita.
+    InvalidConstant(u32),
+    InvalidUtf8,
+    /// Sobran bytes después de la última sección.
+    TrailingBytes(usize),
+    /// Al codificar, un operando, índice o tamaño no cabe en un `u32`.
+    TooLarge(usize),
+    /// Un salto o una etiqueta apunta más allá del final del programa.
+    InvalidTarget(usize),
+    /// El mapa de fuentes no tiene una entrada por instrucción.
+    SourceMapMismatch {
+        entries: usize,
+        instructions: usize,
+    },
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BytecodeError::BadMagic => write!(f, "el archivo no es bytecode de Vainilla Machine"),
+            BytecodeError::UnsupportedVersion(version) => write!(
+                f,
+                "versión de bytecode no soportada: {} (se esperaba {})",
+                version, VERSION
+            ),
+            BytecodeError::Truncated => write!(f, "el archivo de bytecode está incompleto"),
+            BytecodeError::InvalidOpcode(op) => write!(f, "código de operación inválido: {}", op),
+            BytecodeError::InvalidConstantTag(tag) => {
+                write!(f, "tipo de constante inválido: {}", tag)
+            }
+            BytecodeError::InvalidIndex(ix) => write!(f, "índice fuera de rango: {}", ix),
+            BytecodeError::InvalidConstant(ix) => {
+                write!(f, "la constante {} no es del tipo esperado", ix)
+            }
+            BytecodeError::InvalidUtf8 => write!(f, "cadena con UTF-8 inválido"),
+            BytecodeError::TrailingBytes(n) => {
+                write!(f, "{} byte(s) de sobra al final del archivo", n)
+            }
+            BytecodeError::TooLarge(val) => {
+                write!(f, "el valor {} no cabe en el formato de bytecode", val)
+            }
+            BytecodeError::InvalidTarget(target) => {
+                write!(f, "destino fuera del programa: {}", target)
+            }
+            BytecodeError::SourceMapMismatch {
+                entries,
+                instructions,
+            } => write!(
+                f,
+                "el mapa de fuentes tiene {} entrada(s) para {} instrucción(es)",
+                entries, instructions
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {}
+
+/// Indica si los bytes empiezan con el encabezado del formato binario.
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Lee un archivo `.vm` o, si empieza con el encabezado del formato binario,
+/// un `.vmb`. El error ya viene listo para mostrarse, con todos los errores
+/// de ensamblado si los hay.
+pub fn load(path: &str) -> Result<Program, String> {
+    let bytes = fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+    if is_bytecode(&bytes) {
+        return decode(&bytes).map_err(|err| format!("{}: {}", path, err));
+    }
+    if !path.ends_with(".vm") {
+        return Err(format!(
+            "{}: el archivo debe tener extensión .vm o ser bytecode",
+            path
+        ));
+    }
+    let contents = String::from_utf8(bytes).map_err(|err| format!("{}: {}", path, err))?;
+    Parser::with_file(path)
+        .parse_file(&contents)
+        .map_err(|errors| {
+            let rendered: String = errors.iter().map(|error| error.render()).collect();
+            format!(
+                "{}{} error(es) al ensamblar el programa",
+                rendered,
+                errors.len()
+            )
+        })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Constant {
+    Int(i64),
+    Float(f64),
+    /// Índice en la tabla de cadenas.
+    Str(u32),
+}
+
+impl Constant {
+    fn tag(self) -> u8 {
+        match self {
+            Constant::Int(_) => 0,
+            Constant::Float(_) => 1,
+            Constant::Str(_) => 2,
+        }
+    }
+
+    fn bits(self) -> u64 {
+        match self {
+            Constant::Int(i) => i as u64,
+            Constant::Float(f) => f.to_bits(),
+            Constant::Str(ix) => ix as u64,
+        }
+    }
+}
+
+/// Tablas de cadenas y constantes sin repetidos.
+#[derive(Default)]
+struct Pools {
+    strings: Vec<String>,
+    string_ids: HashMap<String, u32>,
+    constants: Vec<Constant>,
+    constant_ids: HashMap<(u8, u64), u32>,
+}
+
+impl Pools {
+    fn string(&mut self, s: &str) -> Result<u32, BytecodeError> {
+        if let Some(&id) = self.string_ids.get(s) {
+            return Ok(id);
+        }
+        let id = to_u32(self.strings.len())?;
+        self.strings.push(s.to_string());
+        self.string_ids.insert(s.to_string(), id);
+        Ok(id)
+    }
+
+    fn constant(&mut self, constant: Constant) -> Result<u32, BytecodeError> {
+        let key = (constant.tag(), constant.bits());
+        if let Some(&id) = self.constant_ids.get(&key) {
+            return Ok(id);
+        }
+        let id = to_u32(self.constants.len())?;
+        self.constants.push(constant);
+        self.constant_ids.insert(key, id);
+        Ok(id)
+    }
+}
+
+fn put_u16(out: &mut Vec<u8>, val: u16) {
+    out.extend_from_slice(&val.to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, val: u32) {
+    out.extend_from_slice(&val.to_le_bytes());
+}
+
+fn to_u32(val: usize) -> Result<u32, BytecodeError> {
+    u32::try_from(val).map_err(|_| BytecodeError::TooLarge(val))
+}
+
+fn put_usize(out: &mut Vec<u8>, val: usize) -> Result<(), BytecodeError> {
+    put_u32(out, to_u32(val)?);
+    Ok(())
+}
+
+/// Código de operación; sigue el orden de las variantes de `Instruction`.
+fn opcode(instr: &Instruction) -> u8 {
+    match instr {
+        Instruction::LoadConstFloat(_) => 0,
+        Instruction::LoadConstInt(_) => 1,
+        Instruction::LoadConstBool(_) => 2,
+        Instruction::LoadConstStr(_) => 3,
+        Instruction::LoadVar(_) => 4,
+        Instruction::StoreVar(_) => 5,
+        Instruction::LoadGlobal(_) => 6,
+        Instruction::StoreGlobal(_) => 7,
+        Instruction::Add => 8,
+        Instruction::Sub => 9,
+        Instruction::Mul => 10,
+        Instruction::Div => 11,
+        Instruction::IDiv => 12,
+        Instruction::Pow => 13,
+        Instruction::Mod => 14,
+        Instruction::Print => 15,
+        Instruction::Read => 16,
+        Instruction::Jmp(_) => 17,
+        Instruction::JmpEq(_) => 18,
+        Instruction::JmpNe(_) => 19,
+        Instruction::JmpGe(_) => 20,
+        Instruction::JmpGt(_) => 21,
+        Instruction::JmpLt(_) => 22,
+        Instruction::JmpLe(_) => 23,
+        Instruction::Call(_) => 24,
+        Instruction::Ret => 25,
+        Instruction::Dup => 26,
+        Instruction::Pop => 27,
+        Instruction::Swap => 28,
+        Instruction::Over => 29,
+        Instruction::Rot => 30,
+        Instruction::Pick(_) => 31,
+        Instruction::Eq => 32,
+        Instruction::Ne => 33,
+        Instruction::Lt => 34,
+        Instruction::Le => 35,
+        Instruction::Gt => 36,
+        Instruction::Ge => 37,
+        Instruction::JmpTrue(_) => 38,
+        Instruction::JmpFalse(_) => 39,
+        Instruction::And => 40,
+        Instruction::Or => 41,
+        Instruction::Not => 42,
+        Instruction::Xor => 43,
+        Instruction::Concat => 44,
+        Instruction::Len => 45,
+        Instruction::Substr => 46,
+        Instruction::ToStr => 47,
+        Instruction::ToNum => 48,
+        Instruction::ToFloat => 49,
+        Instruction::Num => 50,
+        Instruction::Den => 51,
+        Instruction::NewArray(_) => 52,
+        Instruction::ArrayGet => 53,
+        Instruction::ArraySet => 54,
+        Instruction::ArrayLen => 55,
+        Instruction::ArrayPush => 56,
+        Instruction::ArrayPop => 57,
+        Instruction::NewMap => 58,
+        Instruction::MapGet => 59,
+        Instruction::MapSet => 60,
+        Instruction::MapHas => 61,
+        Instruction::MapDel => 62,
+        Instruction::MapKeys => 63,
+    }
+}
+
+/// Operando de la instrucción ya convertido en índice de alguna tabla.
+fn operand(instr: &Instruction, pools: &mut Pools) -> Result<Option<u32>, BytecodeError> {
+    let operand = match instr {
+        Instruction::LoadConstFloat(val) => pools.constant(Constant::Float(*val))?,
+        Instruction::LoadConstInt(val) => pools.constant(Constant::Int(*val))?,
+        Instruction::LoadConstBool(val) => *val as u32,
+        Instruction::LoadConstStr(val) => {
+            let id = pools.string(val)?;
+            pools.constant(Constant::Str(id))?
+        }
+        Instruction::LoadVar(name)
+        | Instruction::StoreVar(name)
+        | Instruction::LoadGlobal(name)
+        | Instruction::StoreGlobal(name) => pools.string(name)?,
+        Instruction::Pick(n) | Instruction::NewArray(n) => to_u32(*n)?,
+        instr => match instr.jump_target() {
+            Some(target) => to_u32(target)?,
+            None => return Ok(None),
+        },
+    };
+    Ok(Some(operand))
+}
+
+/// Convierte el programa al formato binario. Con `debug` se incluyen el
+/// mapa de fuentes y las etiquetas para que los errores y el depurador
+/// puedan mostrar líneas y nombres. Falla si algún operando, índice o
+/// tamaño no cabe en un `u32`.
+pub fn encode(program: &Program, debug: bool) -> Result<Vec<u8>, BytecodeError> {
+    let mut pools = Pools::default();
+
+    let mut code = Vec::new();
+    put_usize(&mut code, program.len())?;
+    for instr in &program.instructions {
+        code.push(opcode(instr));
+        if let Some(operand) = operand(instr, &mut pools)? {
+            put_u32(&mut code, operand);
+        }
+    }
+
+    let mut debug_section = Vec::new();
+    if debug {
+        let source_map = &program.source_map;
+        put_u32(&mut debug_section, pools.string(&source_map.file)?);
+        put_usize(&mut debug_section, source_map.entries.len())?;
+        for entry in &source_map.entries {
+            put_usize(&mut debug_section, entry.line)?;
+            put_u32(&mut debug_section, pools.string(&entry.text)?);
+        }
+        // Ordenadas para que el archivo no dependa del orden del HashMap.
+        let labels: BTreeMap<&String, &usize> = program.labels.iter().collect();
+        put_usize(&mut debug_section, labels.len())?;
+        for (name, target) in labels {
+            put_u32(&mut debug_section, pools.string(name)?);
+            put_usize(&mut debug_section, *target)?;
+        }
+    }
+
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    put_u16(&mut out, VERSION);
+    put_u16(&mut out, if debug { FLAG_DEBUG } else { 0 });
+    put_usize(&mut out, pools.strings.len())?;
+    for s in &pools.strings {
+        put_usize(&mut out, s.len())?;
+        out.extend_from_slice(s.as_bytes());
+    }
+    put_usize(&mut out, pools.constants.len())?;
+    for constant in &pools.constants {
+        out.push(constant.tag());
+        out.extend_from_slice(&constant.bits().to_le_bytes());
+    }
+    out.extend(code);
+    out.extend(debug_section);
+    Ok(out)
+}
+
+/// Lector secuencial sobre los bytes del archivo.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], BytecodeError> {
+        let end = self.pos.checked_add(n).ok_or(BytecodeError::Truncated)?;
+        let slice = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or(BytecodeError::Truncated)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, BytecodeError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, BytecodeError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, BytecodeError> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn usize(&mut self) -> Result<usize, BytecodeError> {
+        Ok(self.u32()? as usize)
+    }
+}
+
+/// Tablas leídas del archivo, con acceso validado por índice.
+struct Tables {
+    strings: Vec<String>,
+    constants: Vec<Constant>,
+}
+
+impl Tables {
+    fn string(&self, ix: u32) -> Result<&str, BytecodeError> {
+        self.strings
+            .get(ix as usize)
+            .map(String::as_str)
+            .ok_or(BytecodeError::InvalidIndex(ix))
+    }
+
+    fn constant(&self, ix: u32) -> Result<Constant, BytecodeError> {
+        self.constants
+            .get(ix as usize)
+            .copied()
+            .ok_or(BytecodeError::InvalidIndex(ix))
+    }
+}
+
+fn decode_instruction(reader: &mut Reader, tables: &Tables) -> Result<Instruction, BytecodeError> {
+    let op = reader.u8()?;
+    let instr = match op {
+        0..=7 | 17..=24 | 31 | 38 | 39 | 52 => {
+            let operand = reader.u32()?;
+            let ix = operand as usize;
+            let name = || tables.string(operand).map(str::to_string);
+            match op {
+                0 => match tables.constant(operand)? {
+                    Constant::Float(val) => Instruction::LoadConstFloat(val),
+                    _ => return Err(BytecodeError::InvalidConstant(operand)),
+                },
+                1 => match tables.constant(operand)? {
+                    Constant::Int(val) => Instruction::LoadConstInt(val),
+                    _ => return Err(BytecodeError::InvalidConstant(operand)),
+                },
+                2 => Instruction::LoadConstBool(operand != 0),
+                3 => match tables.constant(operand)? {
+                    Constant::Str(id) => Instruction::LoadConstStr(tables.string(id)?.to_string()),
+                    _ => return Err(BytecodeError::InvalidConstant(operand)),
+                },
+                4 => Instruction::LoadVar(name()?),
+                5 => Instruction::StoreVar(name()?),
+                6 => Instruction::LoadGlobal(name()?),
+                7 => Instruction::StoreGlobal(name()?),
+                17 => Instruction::Jmp(ix),
+                18 => Instruction::JmpEq(ix),
+                19 => Instruction::JmpNe(ix),
+                20 => Instruction::JmpGe(ix),
+                21 => Instruction::JmpGt(ix),
+                22 => Instruction::JmpLt(ix),
+                23 => Instruction::JmpLe(ix),
+                24 => Instruction::Call(ix),
+                31 => Instruction::Pick(ix),
+                38 => Instruction::JmpTrue(ix),
+                39 => Instruction::JmpFalse(ix),
+                _ => Instruction::NewArray(ix),
+            }
+        }
+        8 => Instruction::Add,
+        9 => Instruction::Sub,
+        10 => Instruction::Mul,
+        11 => Instruction::Div,
+        12 => Instruction::IDiv,
+        13 => Instruction::Pow,
+        14 => Instruction::Mod,
+        15 => Instruction::Print,
+        16 => Instruction::Read,
+        25 => Instruction::Ret,
+        26 => Instruction::Dup,
+        27 => Instruction::Pop,
+        28 => Instruction::Swap,
+        29 => Instruction::Over,
+        30 => Instruction::Rot,
+        32 => Instruction::Eq,
+        33 => Instruction::Ne,
+        34 => Instruction::Lt,
+        35 => Instruction::Le,
+        36 => Instruction::Gt,
+        37 => Instruction::Ge,
+        40 => Instruction::And,
+        41 => Instruction::Or,
+        42 => Instruction::Not,
+        43 => Instruction::Xor,
+        44 => Instruction::Concat,
+        45 => Instruction::Len,
+        46 => Instruction::Substr,
+        47 => Instruction::ToStr,
+        48 => Instruction::ToNum,
+        49 => Instruction::ToFloat,
+        50 => Instruction::Num,
+        51 => Instruction::Den,
+        53 => Instruction::ArrayGet,
+        54 => Instruction::ArraySet,
+        55 => Instruction::ArrayLen,
+        56 => Instruction::ArrayPush,
+        57 => Instruction::ArrayPop,
+        58 => Instruction::NewMap,
+        59 => Instruction::MapGet,
+        60 => Instruction::MapSet,
+        61 => Instruction::MapHas,
+        62 => Instruction::MapDel,
+        63 => Instruction::MapKeys,
+        op => return Err(BytecodeError::InvalidOpcode(op)),
+    };
+    Ok(instr)
+}
+
+/// Reconstruye un programa a partir de su forma binaria. Sin sección de
+/// depuración el programa queda sin etiquetas ni mapa de fuentes.
+pub fn decode(bytes: &[u8]) -> Result<Program, BytecodeError> {
+    let mut reader = Reader { bytes, pos: 0 };
+    if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(BytecodeError::BadMagic);
+    }
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(BytecodeError::UnsupportedVersion(version));
+    }
+    let flags = reader.u16()?;
+
+    let mut tables = Tables {
+        strings: Vec::new(),
+        constants: Vec::new(),
+    };
+    for _ in 0..reader.u32()? {
+        let len = reader.usize()?;
+        let s = std::str::from_utf8(reader.take(len)?).map_err(|_| BytecodeError::InvalidUtf8)?;
+        tables.strings.push(s.to_string());
+    }
+    for _ in 0..reader.u32()? {
+        let tag = reader.u8()?;
+        let bits = reader.u64()?;
+        let constant = match tag {
+            0 => Constant::Int(bits as i64),
+            1 => Constant::Float(f64::from_bits(bits)),
+            2 => Constant::Str(bits as u32),
+            tag => return Err(BytecodeError::InvalidConstantTag(tag)),
+        };
+        tables.constants.push(constant);
+    }
+
+    let mut program = Program::default();
+    for _ in 0..reader.u32()? {
+        let instr = decode_instruction(&mut reader, &tables)?;
+        program.instructions.push(instr);
+    }
+    // Se puede saltar justo al final del programa, que es terminar.
+    let len = program.len();
+    let check_target = |target: usize| match target <= len {
+        true => Ok(target),
+        false => Err(BytecodeError::InvalidTarget(target)),
+    };
+    for instr in &program.instructions {
+        if let Some(target) = instr.jump_target() {
+            check_target(target)?;
+        }
+    }
+
+    if flags & FLAG_DEBUG != 0 {
+        let file = tables.string(reader.u32()?)?;
+        program.source_map = SourceMap::new(file);
+        for _ in 0..reader.u32()? {
+            let line = reader.usize()?;
+            let text = tables.string(reader.u32()?)?;
+            program.source_map.push(line, text);
+        }
+        // Un programa armado sin ensamblador no tiene mapa de fuentes.
+        let entries = program.source_map.entries.len();
+        if entries != 0 && entries != len {
+            return Err(BytecodeError::SourceMapMismatch {
+                entries,
+                instructions: len,
+            });
+        }
+        for _ in 0..reader.u32()? {
+            let name = tables.string(reader.u32()?)?;
+            let target = check_target(reader.usize()?)?;
+            program.labels.insert(name.to_string(), target);
+        }
+    }
+
+    match bytes.len() - reader.pos {
+        0 => Ok(program),
+        n => Err(BytecodeError::TrailingBytes(n)),
+    }
+}
+
+/// Escribe una cadena como literal del ensamblador.
+fn quote(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Texto ensamblable equivalente al programa. Los saltos a direcciones sin
+/// etiqueta reciben una generada (`L<dirección>`); con mapa de fuentes cada
+/// instrucción indica su línea original en un comentario.
+pub fn disassemble(program: &Program) -> String {
+    let mut names: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    for (name, target) in &program.labels {
+        names.entry(*target).or_default().push(name.clone());
+    }
+    for instr in &program.instructions {
+        if let Some(target) = instr.jump_target() {
+            names
+                .entry(target)
+                .or_insert_with(|| vec![format!("L{}", target)]);
+        }
+    }
+    for labels in names.values_mut() {
+        labels.sort();
+    }
+    let target_name = |target: usize| names[&target][0].clone();
+
+    let mut out = String::new();
+    if !program.source_map.file.is_empty() {
+        out.push_str(&format!("; {}\n", program.source_map.file));
+    }
+    for (ix, instr) in program.instructions.iter().enumerate() {
+        for label in names.get(&ix).into_iter().flatten() {
+            out.push_str(&format!("{}:\n", label));
+        }
+        let operand = match instr {
+            Instruction::LoadConstFloat(val) => Some(val.to_string()),
+            Instruction::LoadConstInt(val) => Some(val.to_string()),
+            Instruction::LoadConstBool(val) => Some(val.to_string()),
+            Instruction::LoadConstStr(val) => Some(quote(val)),
+            Instruction::LoadVar(name)
+            | Instruction::StoreVar(name)
+            | Instruction::LoadGlobal(name)
+            | Instruction::StoreGlobal(name) => Some(name.clone()),
+            Instruction::Pick(n) | Instruction::NewArray(n) => Some(n.to_string()),
+            instr => instr.jump_target().map(target_name),
+        };
+        let text = match operand {
+            Some(operand) => format!("{} {}", instr.mnemonic(), operand),
+            None => instr.mnemonic().to_string(),
+        };
+        match program.line_of(ix) {
+            Some(line) => out.push_str(&format!("{:<32}; línea {}\n", text, line)),
+            None => out.push_str(&format!("{}\n", text)),
+        }
+    }
+    // Etiquetas al final del programa (por ejemplo, el destino de un salto
+    // que termina la ejecución).
+    for label in names.get(&program.len()).into_iter().flatten() {
+        out.push_str(&format!("{}:\n", label));
+    }
+    out
+}
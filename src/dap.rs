@@ -0,0 +1,504 @@
+use super::bytecode;
+use super::debugger::{Debugger, Stop};
+use super::program::Program;
+use super::protocol::{read_message, write_message};
+use super::vm::{self, HeapObject, VmConfig, VmError, VM};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+/// La máquina tiene un solo hilo de ejecución.
+const THREAD_ID: u64 = 1;
+/// Instrucciones que se ejecutan entre dos revisiones de los mensajes del
+/// cliente, para poder atender `pause` mientras el programa corre.
+const CHUNK: u64 = 10_000;
+/// Pasos que se pueden deshacer con `stepBack` y `reverseContinue`.
+const HISTORY_LIMIT: usize = 10_000;
+
+// Números de `variablesReference`: las variables globales, la pila, las
+// locales de cada marco (por índice en la pila de llamadas) y cada objeto
+// del heap (por índice).
+const GLOBALS_REF: usize = 1;
+const STACK_REF: usize = 2;
+const LOCALS_REF: usize = 3;
+const HEAP_REF: usize = 1 << 24;
+
+/// Salida de `PRINT`, que se reenvía al cliente como eventos `output`.
+#[derive(Clone, Default)]
+struct Captured(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Hasta dónde corre el programa entre dos detenciones.
+#[derive(Debug, Clone, Copy)]
+enum Running {
+    /// Hasta un punto de ruptura o el final.
+    Continue,
+    /// Hasta que la pila de llamadas baje a esta profundidad, para `next`
+    /// sobre un `CALL` y para `stepOut`.
+    Depth(usize),
+}
+
+/// Atiende una sesión del Debug Adapter Protocol: lee las peticiones de
+/// `input` en un hilo aparte y escribe respuestas y eventos en `output`.
+/// Termina con `disconnect` o cuando se acaba la entrada.
+pub fn serve<R, W>(input: R, output: W) -> io::Result<()>
+where
+    R: BufRead + Send + 'static,
+    W: Write,
+{
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = input;
+        while let Ok(Some(message)) = read_message(&mut input) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    Session::new(output).serve(receiver)
+}
+
+struct Session<W> {
+    output: W,
+    seq: u64,
+    vm: Option<VM>,
+    debugger: Debugger,
+    printed: Captured,
+    stop_on_entry: bool,
+    running: Option<Running>,
+    finished: bool,
+}
+
+impl<W: Write> Session<W> {
+    fn new(output: W) -> Self {
+        Session {
+            output,
+            seq: 0,
+            vm: None,
+            debugger: Debugger::new(),
+            printed: Captured::default(),
+            stop_on_entry: false,
+            running: None,
+            finished: false,
+        }
+    }
+
+    fn serve(mut self, requests: Receiver<Value>) -> io::Result<()> {
+        while !self.finished {
+            let request = if self.running.is_some() {
+                match requests.try_recv() {
+                    Ok(request) => Some(request),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            } else {
+                match requests.recv() {
+                    Ok(request) => Some(request),
+                    Err(_) => break,
+                }
+            };
+            match request {
+                Some(request) => self.handle(&request)?,
+                None => self.run_chunk()?,
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.output, &message)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn handle(&mut self, request: &Value) -> io::Result<()> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let args = &request["arguments"];
+        let result = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsEvaluateForHovers": true,
+                "supportsStepBack": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" => self.launch(args),
+            "setBreakpoints" => self.set_breakpoints(args),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "principal" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => self.scopes(args),
+            "variables" => self.variables(args),
+            "evaluate" => self.evaluate(args),
+            "continue" => self.vm().map(|_| json!({ "allThreadsContinued": true })),
+            "configurationDone" | "next" | "stepIn" | "stepOut" | "stepBack"
+            | "reverseContinue" | "pause" => self.vm().map(|_| json!({})),
+            "disconnect" | "terminate" => Ok(json!({})),
+            _ => Err(format!("petición no soportada: {}", command)),
+        };
+        let success = result.is_ok();
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": success,
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)?;
+        if success {
+            self.after(command)?;
+        }
+        Ok(())
+    }
+
+    /// Efectos de la petición que deben llegar al cliente después de la
+    /// respuesta, como el evento `stopped` de un paso.
+    fn after(&mut self, command: &str) -> io::Result<()> {
+        match command {
+            "launch" => self.event("initialized", json!({})),
+            "configurationDone" if self.stop_on_entry => self.stopped("entry", None, None),
+            "configurationDone" => match self.debugger.breakpoint_here(self.vm.as_ref().unwrap()) {
+                Some(stop) => self.report(Ok(stop)),
+                None => {
+                    self.running = Some(Running::Continue);
+                    Ok(())
+                }
+            },
+            "continue" => {
+                self.running = Some(Running::Continue);
+                Ok(())
+            }
+            "next" => {
+                let vm = self.vm.as_mut().unwrap();
+                if matches!(vm.current_instruction(), Some(vm::Instruction::Call(_))) {
+                    self.running = Some(Running::Depth(vm.call_stack().len()));
+                    return Ok(());
+                }
+                let stop = self.debugger.step(vm);
+                self.report(stop)
+            }
+            "stepIn" => {
+                let stop = self.debugger.step(self.vm.as_mut().unwrap());
+                self.report(stop)
+            }
+            "stepOut" => {
+                let depth = self.vm.as_ref().unwrap().call_stack().len();
+                self.running = Some(match depth.checked_sub(1) {
+                    Some(depth) => Running::Depth(depth),
+                    None => Running::Continue,
+                });
+                Ok(())
+            }
+            "stepBack" => {
+                let vm = self.vm.as_mut().unwrap();
+                let stop = match vm.step_back() {
+                    true => Stop::Step,
+                    false => Stop::HistoryStart,
+                };
+                self.debugger.sync(vm);
+                self.report(Ok(stop))
+            }
+            "reverseContinue" => {
+                let stop = self.debugger.reverse(self.vm.as_mut().unwrap());
+                self.report(Ok(stop))
+            }
+            "pause" => {
+                self.running = None;
+                self.stopped("pause", None, None)
+            }
+            "disconnect" => {
+                self.finished = true;
+                Ok(())
+            }
+            "terminate" => {
+                self.finished = true;
+                self.event("terminated", json!({}))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn vm(&self) -> Result<&VM, String> {
+        self.vm
+            .as_ref()
+            .ok_or_else(|| "no se ha cargado ningún programa".to_string())
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let path = args["program"]
+            .as_str()
+            .ok_or("falta el argumento `program`")?;
+        let config = VmConfig {
+            history_limit: HISTORY_LIMIT,
+            ..VmConfig::default()
+        };
+        let mut vm = VM::with_config(bytecode::load(path)?, config);
+        // La entrada estándar del adaptador es el canal del protocolo, así
+        // que `READ` solo puede leer de un archivo.
+        match args["input"].as_str() {
+            Some(input) => {
+                let file = fs::File::open(input).map_err(|err| format!("{}: {}", input, err))?;
+                vm.set_input(io::BufReader::new(file));
+            }
+            None => vm.set_input(io::empty()),
+        }
+        vm.set_output(self.printed.clone());
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        self.vm = Some(vm);
+        Ok(json!({}))
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value, String> {
+        let vm = self.vm.as_ref().ok_or("no se ha cargado ningún programa")?;
+        self.debugger.clear_breakpoints();
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let breakpoints: Vec<Value> = requested
+            .iter()
+            .map(|requested| {
+                let line = requested["line"].as_u64().unwrap_or_default();
+                let spec = match requested["condition"].as_str() {
+                    Some(condition) if !condition.trim().is_empty() => {
+                        format!("{} if {}", line, condition)
+                    }
+                    _ => line.to_string(),
+                };
+                match self.debugger.add_breakpoint(vm.program(), &spec) {
+                    Ok(bp) => json!({
+                        "id": bp.id,
+                        "verified": true,
+                        "line": vm.program().line_of(bp.address),
+                        "source": source(vm.program()),
+                    }),
+                    Err(error) => json!({
+                        "verified": false,
+                        "line": line,
+                        "message": error.to_string(),
+                    }),
+                }
+            })
+            .collect();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn stack_trace(&self) -> Result<Value, String> {
+        let vm = self.vm()?;
+        let call_stack = vm.call_stack();
+        let mut frames = Vec::new();
+        let mut ip = vm.ip();
+        for depth in (0..=call_stack.len()).rev() {
+            let frame = depth.checked_sub(1).map(|ix| &call_stack[ix]);
+            frames.push(json!({
+                "id": call_stack.len() - depth,
+                "name": vm.function_name(frame),
+                "source": source(vm.program()),
+                "line": vm.program().line_of(ip).unwrap_or_default(),
+                "column": 1,
+                "instructionPointerReference": ip.to_string(),
+            }));
+            if let Some(frame) = frame {
+                ip = frame.return_addr - 1;
+            }
+        }
+        Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+    }
+
+    fn scopes(&self, args: &Value) -> Result<Value, String> {
+        let vm = self.vm()?;
+        let id = args["frameId"].as_u64().unwrap_or_default() as usize;
+        let depth = vm
+            .call_stack()
+            .len()
+            .checked_sub(id)
+            .ok_or_else(|| format!("no existe el marco {}", id))?;
+        let mut scopes = Vec::new();
+        if let Some(ix) = depth.checked_sub(1) {
+            scopes.push(scope("Locales", LOCALS_REF + ix, false));
+        }
+        scopes.push(scope("Globales", GLOBALS_REF, false));
+        scopes.push(scope("Pila", STACK_REF, true));
+        Ok(json!({ "scopes": scopes }))
+    }
+
+    fn variables(&self, args: &Value) -> Result<Value, String> {
+        let vm = self.vm()?;
+        let reference = args["variablesReference"].as_u64().unwrap_or_default() as usize;
+        let heap = vm.heap();
+        let named: Vec<(String, &vm::Value)> = match reference {
+            GLOBALS_REF => sorted(vm.globals()),
+            STACK_REF => vm
+                .stack()
+                .iter()
+                .enumerate()
+                .map(|(ix, val)| (ix.to_string(), val))
+                .collect(),
+            r if r >= HEAP_REF => {
+                match heap
+                    .iter()
+                    .find(|(object, _)| object.index() == r - HEAP_REF)
+                {
+                    Some((_, HeapObject::Array(items))) => items
+                        .iter()
+                        .enumerate()
+                        .map(|(ix, val)| (format!("[{}]", ix), val))
+                        .collect(),
+                    Some((_, HeapObject::Map(entries))) => entries
+                        .iter()
+                        .map(|(key, val)| (key.to_string(), val))
+                        .collect(),
+                    None => Vec::new(),
+                }
+            }
+            r if r >= LOCALS_REF => match vm.call_stack().get(r - LOCALS_REF) {
+                Some(frame) => sorted(&frame.locals),
+                None => Vec::new(),
+            },
+            _ => Vec::new(),
+        };
+        let variables: Vec<Value> = named
+            .into_iter()
+            .map(|(name, val)| {
+                json!({
+                    "name": name,
+                    "value": heap.repr(val),
+                    "type": val.type_name(),
+                    "variablesReference": expandable(val),
+                })
+            })
+            .collect();
+        Ok(json!({ "variables": variables }))
+    }
+
+    /// Solo se evalúan nombres de variables.
+    fn evaluate(&self, args: &Value) -> Result<Value, String> {
+        let vm = self.vm()?;
+        let name = args["expression"].as_str().unwrap_or_default().trim();
+        let val = vm
+            .lookup_var(name)
+            .ok_or_else(|| format!("variable {} no encontrada", name))?;
+        Ok(json!({
+            "result": vm.heap().repr(val),
+            "type": val.type_name(),
+            "variablesReference": expandable(val),
+        }))
+    }
+
+    fn run_chunk(&mut self) -> io::Result<()> {
+        let Some(running) = self.running else {
+            return Ok(());
+        };
+        let vm = self.vm.as_mut().unwrap();
+        let mut steps = 0;
+        let mut arrived = false;
+        let stop = self.debugger.run_until(vm, |vm| {
+            steps += 1;
+            arrived = match running {
+                Running::Continue => false,
+                Running::Depth(depth) => vm.call_stack().len() <= depth,
+            };
+            arrived || steps >= CHUNK
+        });
+        if matches!(stop, Ok(Stop::Step)) && !arrived {
+            return self.flush_output();
+        }
+        self.report(stop)
+    }
+
+    /// Avisa al cliente por qué se detuvo la ejecución.
+    fn report(&mut self, stop: Result<Stop, VmError>) -> io::Result<()> {
+        self.running = None;
+        self.flush_output()?;
+        match stop {
+            Ok(Stop::Step) | Ok(Stop::HistoryStart) => self.stopped("step", None, None),
+            Ok(Stop::Breakpoint(id)) => self.stopped("breakpoint", Some(id), None),
+            Ok(Stop::Watchpoint { .. }) => self.stopped("data breakpoint", None, None),
+            Ok(Stop::Halted) => {
+                self.event("exited", json!({ "exitCode": 0 }))?;
+                self.event("terminated", json!({}))
+            }
+            Err(error) => {
+                self.event(
+                    "output",
+                    json!({ "category": "stderr", "output": format!("{}\n", error) }),
+                )?;
+                self.stopped("exception", None, Some(error.kind.to_string()))
+            }
+        }
+    }
+
+    fn stopped(
+        &mut self,
+        reason: &str,
+        hit: Option<usize>,
+        text: Option<String>,
+    ) -> io::Result<()> {
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(id) = hit {
+            body["hitBreakpointIds"] = json!([id]);
+        }
+        if let Some(text) = text {
+            body["text"] = json!(text);
+        }
+        self.event("stopped", body)
+    }
+
+    fn flush_output(&mut self) -> io::Result<()> {
+        let printed = std::mem::take(&mut *self.printed.0.borrow_mut());
+        if printed.is_empty() {
+            return Ok(());
+        }
+        let output = String::from_utf8_lossy(&printed).into_owned();
+        self.event("output", json!({ "category": "stdout", "output": output }))
+    }
+}
+
+fn source(program: &Program) -> Value {
+    let path = &program.source_map.file;
+    let name = std::path::Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    json!({ "name": name, "path": path })
+}
+
+fn scope(name: &str, reference: usize, expensive: bool) -> Value {
+    json!({ "name": name, "variablesReference": reference, "expensive": expensive })
+}
+
+fn sorted<V>(vars: &std::collections::HashMap<String, V>) -> Vec<(String, &V)> {
+    vars.iter()
+        .collect::<BTreeMap<_, _>>()
+        .into_iter()
+        .map(|(name, val)| (name.clone(), val))
+        .collect()
+}
+
+/// Los arreglos y mapas se pueden expandir en el cliente.
+fn expandable(val: &vm::Value) -> usize {
+    val.heap_ref().map_or(0, |r| HEAP_REF + r.index())
+}
@@ -0,0 +1,466 @@
+use super::program::Program;
+use super::vm::{self, StepOutcome, Value, VmError, VM};
+use std::cmp::Ordering;
+use std::fmt;
+
+mod repl;
+
+pub use repl::{Command, InfoTopic, Repl};
+
+/// Dónde se detiene un punto de ruptura.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    Label(String),
+    Instruction(usize),
+    /// Línea del archivo fuente.
+    Line(usize),
+}
+
+impl Location {
+    /// `#N` es un índice de instrucción, un número solo es una línea del
+    /// archivo y cualquier otra cosa es una etiqueta.
+    pub fn parse(text: &str) -> Location {
+        if let Some(ix) = text.strip_prefix('#').and_then(|ix| ix.parse().ok()) {
+            return Location::Instruction(ix);
+        }
+        match text.parse() {
+            Ok(line) => Location::Line(line),
+            Err(_) => Location::Label(text.to_string()),
+        }
+    }
+
+    /// Dirección de la instrucción en la que se detiene. Una línea sin
+    /// instrucciones se recorre a la siguiente que sí tenga.
+    pub fn resolve(&self, program: &Program) -> Result<usize, DebugError> {
+        match self {
+            Location::Label(name) => program
+                .labels
+                .get(name)
+                .copied()
+                .filter(|&ix| ix < program.len())
+                .ok_or_else(|| DebugError::UnknownLabel(name.clone())),
+            Location::Instruction(ix) if *ix < program.len() => Ok(*ix),
+            Location::Instruction(ix) => Err(DebugError::InvalidAddress(*ix)),
+            Location::Line(line) => program
+                .source_map
+                .entries
+                .iter()
+                .position(|entry| entry.line >= *line)
+                .ok_or(DebugError::NoCodeAtLine(*line)),
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Label(name) => write!(f, "{}", name),
+            Location::Instruction(ix) => write!(f, "#{}", ix),
+            Location::Line(line) => write!(f, "línea {}", line),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CompareOp {
+    /// Los operadores de dos caracteres van primero para que `<=` no se
+    /// confunda con `<`.
+    const ALL: [(&'static str, CompareOp); 6] = [
+        ("==", CompareOp::Eq),
+        ("!=", CompareOp::Ne),
+        ("<=", CompareOp::Le),
+        (">=", CompareOp::Ge),
+        ("<", CompareOp::Lt),
+        (">", CompareOp::Gt),
+    ];
+
+    pub fn symbol(self) -> &'static str {
+        CompareOp::ALL
+            .iter()
+            .find(|(_, op)| *op == self)
+            .map(|(symbol, _)| *symbol)
+            .unwrap()
+    }
+}
+
+/// Lee un valor escrito en el depurador: `true`, `false`, una cadena entre
+/// comillas o un número. Sin `bigint` los enteros deben caber en 64 bits.
+pub fn parse_literal(text: &str, bigint: bool) -> Result<Value, DebugError> {
+    match text {
+        "true" => Ok(Value::Bool(true)),
+        "false" => Ok(Value::Bool(false)),
+        _ => match text.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+            Some(s) => Ok(Value::Str(s.to_string())),
+            None => match vm::parse_number(text, true) {
+                Some(Value::BigInt(_)) if !bigint => Err(DebugError::OutOfRange(text.to_string())),
+                Some(val) => Ok(val),
+                None => Err(DebugError::InvalidValue(text.to_string())),
+            },
+        },
+    }
+}
+
+/// Condición de un punto de ruptura: `variable operador literal`.
+#[derive(Debug, Clone)]
+pub struct Condition {
+    pub var: String,
+    pub op: CompareOp,
+    pub value: Value,
+}
+
+impl Condition {
+    pub fn parse(text: &str) -> Result<Condition, DebugError> {
+        let invalid = || DebugError::InvalidCondition(text.to_string());
+        let (var, op, literal) = CompareOp::ALL
+            .iter()
+            .find_map(|(symbol, op)| {
+                text.split_once(symbol)
+                    .map(|(var, literal)| (var.trim(), *op, literal.trim()))
+            })
+            .ok_or_else(invalid)?;
+        if var.is_empty() || var.contains(char::is_whitespace) {
+            return Err(invalid());
+        }
+        Ok(Condition {
+            var: var.to_string(),
+            op,
+            // La condición solo compara, así que admite cualquier entero.
+            value: parse_literal(literal, true).map_err(|_| invalid())?,
+        })
+    }
+
+    /// Evalúa la condición con las variables visibles en la máquina. Una
+    /// variable inexistente o de un tipo incomparable no cumple nada salvo
+    /// `!=`.
+    pub fn holds(&self, vm: &VM) -> bool {
+        let Some(val) = vm.lookup_var(&self.var) else {
+            return false;
+        };
+        match self.op {
+            CompareOp::Eq => vm.heap().equals(val, &self.value) == Ok(true),
+            CompareOp::Ne => vm.heap().equals(val, &self.value) != Ok(true),
+            op => {
+                let Ok(Some(ordering)) = val.compare(&self.value) else {
+                    return false;
+                };
+                match op {
+                    CompareOp::Lt => ordering == Ordering::Less,
+                    CompareOp::Le => ordering != Ordering::Greater,
+                    CompareOp::Gt => ordering == Ordering::Greater,
+                    _ => ordering != Ordering::Less,
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.var, self.op.symbol(), self.value.repr())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub location: Location,
+    /// Instrucción en la que se detiene.
+    pub address: usize,
+    pub condition: Option<Condition>,
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{} en {} (instrucción {})",
+            self.id, self.location, self.address
+        )?;
+        if let Some(condition) = &self.condition {
+            write!(f, " si {}", condition)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    /// Cada vez que se escribe la variable, aunque el valor no cambie.
+    Write,
+    /// Cuando el valor visible de la variable cambia, incluido el contenido
+    /// de un arreglo o mapa.
+    Change,
+}
+
+#[derive(Debug, Clone)]
+pub struct Watchpoint {
+    pub id: usize,
+    pub var: String,
+    pub kind: WatchKind,
+    /// Representación del último valor visto (`None` si no existía).
+    last: Option<String>,
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            WatchKind::Write => "escritura",
+            WatchKind::Change => "cambio",
+        };
+        write!(f, "#{} {} de {}", self.id, kind, self.var)
+    }
+}
+
+/// Motivo por el que se detuvo la ejecución.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    /// El programa terminó.
+    Halted,
+    Breakpoint(usize),
+    Watchpoint {
+        id: usize,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    /// Al retroceder se llegó al paso más antiguo del historial.
+    HistoryStart,
+    /// Terminó un `step` o `next` sin encontrar ningún punto.
+    Step,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebugError {
+    UnknownLabel(String),
+    InvalidAddress(usize),
+    /// No hay instrucciones en la línea indicada ni después de ella.
+    NoCodeAtLine(usize),
+    InvalidCondition(String),
+    /// No existe un punto de ruptura ni de observación con ese número.
+    UnknownPoint(usize),
+    InvalidValue(String),
+    /// Entero que no cabe en 64 bits con la máquina sin `--bigint`.
+    OutOfRange(String),
+    UnknownCommand(String),
+    /// Faltan argumentos o sobran; lleva la forma correcta del comando.
+    Usage(&'static str),
+}
+
+impl fmt::Display for DebugError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DebugError::UnknownLabel(name) => write!(f, "la etiqueta {} no existe", name),
+            DebugError::InvalidAddress(ix) => write!(f, "no existe la instrucción {}", ix),
+            DebugError::NoCodeAtLine(line) => {
+                write!(f, "no hay instrucciones en la línea {} ni después", line)
+            }
+            DebugError::InvalidCondition(text) => write!(
+                f,
+                "condición inválida: {} (se esperaba `variable op valor`)",
+                text
+            ),
+            DebugError::UnknownPoint(id) => write!(f, "no existe el punto #{}", id),
+            DebugError::InvalidValue(text) => write!(f, "valor inválido: {}", text),
+            DebugError::OutOfRange(text) => write!(
+                f,
+                "el entero {} no cabe en 64 bits (ejecute con --bigint)",
+                text
+            ),
+            DebugError::UnknownCommand(name) => {
+                write!(f, "comando desconocido: {} (escriba `help`)", name)
+            }
+            DebugError::Usage(usage) => write!(f, "uso: {}", usage),
+        }
+    }
+}
+
+impl std::error::Error for DebugError {}
+
+/// Puntos de ruptura y de observación sobre una máquina.
+#[derive(Debug, Clone, Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    next_id: usize,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger::default()
+    }
+
+    fn next_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Agrega un punto de ruptura escrito como `ubicación [if condición]`,
+    /// por ejemplo `ciclo if i == 3`.
+    pub fn add_breakpoint(
+        &mut self,
+        program: &Program,
+        spec: &str,
+    ) -> Result<&Breakpoint, DebugError> {
+        let (location, condition) = match spec.split_once(" if ") {
+            Some((location, condition)) => (location, Some(Condition::parse(condition)?)),
+            None => (spec, None),
+        };
+        let location = Location::parse(location.trim());
+        let address = location.resolve(program)?;
+        let id = self.next_id();
+        self.breakpoints.push(Breakpoint {
+            id,
+            location,
+            address,
+            condition,
+        });
+        Ok(self.breakpoints.last().unwrap())
+    }
+
+    pub fn add_watchpoint(&mut self, vm: &VM, var: &str, kind: WatchKind) -> &Watchpoint {
+        let id = self.next_id();
+        self.watchpoints.push(Watchpoint {
+            id,
+            var: var.to_string(),
+            kind,
+            last: current(vm, var),
+        });
+        self.watchpoints.last().unwrap()
+    }
+
+    pub fn remove(&mut self, id: usize) -> Result<(), DebugError> {
+        let before = self.breakpoints.len() + self.watchpoints.len();
+        self.breakpoints.retain(|bp| bp.id != id);
+        self.watchpoints.retain(|wp| wp.id != id);
+        if self.breakpoints.len() + self.watchpoints.len() == before {
+            return Err(DebugError::UnknownPoint(id));
+        }
+        Ok(())
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Vuelve a tomar los valores de referencia de los puntos de
+    /// observación; se usa cuando el estado cambió sin pasar por `resume`.
+    pub fn sync(&mut self, vm: &VM) {
+        for wp in &mut self.watchpoints {
+            wp.last = current(vm, &wp.var);
+        }
+    }
+
+    /// Punto de ruptura en la instrucción actual, antes de ejecutarla.
+    /// `resume` solo revisa después de cada paso, así que al arrancar un
+    /// programa hay que preguntar primero por la instrucción inicial.
+    pub fn breakpoint_here(&self, vm: &VM) -> Option<Stop> {
+        self.breakpoint_at(vm).map(Stop::Breakpoint)
+    }
+
+    fn breakpoint_at(&self, vm: &VM) -> Option<usize> {
+        self.breakpoints
+            .iter()
+            .find(|bp| bp.address == vm.ip() && bp.condition.as_ref().is_none_or(|c| c.holds(vm)))
+            .map(|bp| bp.id)
+    }
+
+    /// Revisa los puntos después de un paso y actualiza los valores vistos.
+    fn check(&mut self, vm: &VM) -> Option<Stop> {
+        let mut hit = None;
+        for wp in &mut self.watchpoints {
+            let value = current(vm, &wp.var);
+            let triggered = match wp.kind {
+                WatchKind::Write => vm.last_write() == Some(wp.var.as_str()),
+                WatchKind::Change => value != wp.last,
+            };
+            let old = std::mem::replace(&mut wp.last, value.clone());
+            if triggered && hit.is_none() {
+                hit = Some(Stop::Watchpoint {
+                    id: wp.id,
+                    old,
+                    new: value,
+                });
+            }
+        }
+        hit.or_else(|| self.breakpoint_at(vm).map(Stop::Breakpoint))
+    }
+
+    /// Ejecuta hasta que se cumpla un punto, hasta que `done` devuelva
+    /// `true` (y entonces el motivo es `Stop::Step`) o hasta que el
+    /// programa termine.
+    pub fn run_until<F>(&mut self, vm: &mut VM, mut done: F) -> Result<Stop, VmError>
+    where
+        F: FnMut(&VM) -> bool,
+    {
+        let mut stop = None;
+        let outcome = vm.run_until(|vm| {
+            stop = self.check(vm).or_else(|| done(vm).then_some(Stop::Step));
+            stop.is_some()
+        });
+        match outcome {
+            Ok(StepOutcome::Halted) => Ok(Stop::Halted),
+            Ok(StepOutcome::Continue) => Ok(stop.unwrap_or(Stop::Halted)),
+            Err(error) => {
+                self.sync(vm);
+                Err(error)
+            }
+        }
+    }
+
+    /// Ejecuta hasta el siguiente punto de ruptura u observación que se
+    /// cumpla, o hasta que el programa termine.
+    pub fn resume(&mut self, vm: &mut VM) -> Result<Stop, VmError> {
+        self.run_until(vm, |_| false)
+    }
+
+    /// Ejecuta una sola instrucción.
+    pub fn step(&mut self, vm: &mut VM) -> Result<Stop, VmError> {
+        self.run_until(vm, |_| true)
+    }
+
+    /// Como `step`, pero un `CALL` se ejecuta completo hasta que la
+    /// subrutina regresa, salvo que antes se cumpla algún punto.
+    pub fn step_over(&mut self, vm: &mut VM) -> Result<Stop, VmError> {
+        if !matches!(vm.current_instruction(), Some(vm::Instruction::Call(_))) {
+            return self.step(vm);
+        }
+        let depth = vm.call_stack().len();
+        self.run_until(vm, |vm| vm.call_stack().len() <= depth)
+    }
+
+    /// Retrocede hasta el punto de ruptura anterior o hasta el inicio del
+    /// historial.
+    pub fn reverse(&mut self, vm: &mut VM) -> Stop {
+        let stop = loop {
+            if !vm.step_back() {
+                break Stop::HistoryStart;
+            }
+            if let Some(id) = self.breakpoint_at(vm) {
+                break Stop::Breakpoint(id);
+            }
+        };
+        self.sync(vm);
+        stop
+    }
+}
+
+/// Representación del valor visible de la variable.
+fn current(vm: &VM, var: &str) -> Option<String> {
+    vm.lookup_var(var).map(|val| vm.heap().repr(val))
+}
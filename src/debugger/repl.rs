@@ -0,0 +1,384 @@
+use super::{parse_literal, DebugError, Debugger, Stop, WatchKind};
+use crate::vm::{VmError, VM};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+const HELP: &str = "\
+Comandos:
+  step [n] (s)          ejecuta n instrucciones (1 por omisión)
+  next (n)              como step, pero ejecuta un CALL completo
+  continue (c)          continúa hasta un punto de ruptura o el final
+  back [n]              deshace n instrucciones
+  back line <línea>     deshace instrucciones hasta llegar a la línea
+  reverse               retrocede hasta el punto de ruptura anterior
+  print <var> (p)       muestra el valor de una variable
+  set <var> <valor>     cambia el valor de una variable
+  stack                 muestra la pila
+  vars                  muestra las variables globales y locales
+  list [n] (l)          muestra n instrucciones alrededor de la actual
+  info labels           muestra las etiquetas del programa
+  info breakpoints      muestra los puntos de ruptura y de observación
+  info frames           muestra la pila de llamadas
+  info heap             muestra los objetos del heap
+  break <ubicación> [if <var> <op> <valor>] (b)
+                        agrega un punto de ruptura en una etiqueta, una
+                        línea o una instrucción (#n)
+  watch [write] <var>   se detiene cuando la variable cambia de valor o,
+                        con write, cada vez que se escribe
+  delete <n> (d)        elimina un punto de ruptura o de observación
+  gc                    ejecuta el recolector de basura
+  help (h)              muestra esta ayuda
+  quit (q)              termina la depuración
+Una línea vacía repite el comando anterior.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InfoTopic {
+    Labels,
+    Breakpoints,
+    Frames,
+    Heap,
+}
+
+/// Comando del depurador interactivo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Step(usize),
+    Next,
+    Continue,
+    Back(usize),
+    BackToLine(usize),
+    Reverse,
+    Print(String),
+    Set(String, String),
+    Stack,
+    Vars,
+    List(usize),
+    Info(InfoTopic),
+    Break(String),
+    Watch(String, WatchKind),
+    Delete(usize),
+    Gc,
+    Help,
+    Quit,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, DebugError> {
+        let line = line.trim();
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        let args: Vec<&str> = rest.split_whitespace().collect();
+        let number = |usage| match args.as_slice() {
+            [n] => n.parse().map_err(|_| DebugError::Usage(usage)),
+            _ => Err(DebugError::Usage(usage)),
+        };
+        let count = |usage| match args.as_slice() {
+            [] => Ok(1),
+            _ => number(usage),
+        };
+        let none = |command| match args.as_slice() {
+            [] => Ok(command),
+            _ => Err(DebugError::Usage(name_of(&command))),
+        };
+        match name {
+            "step" | "s" => count("step [n]").map(Command::Step),
+            "next" | "n" => none(Command::Next),
+            "continue" | "c" => none(Command::Continue),
+            "back" => match args.as_slice() {
+                ["line", line] => line
+                    .parse()
+                    .map(Command::BackToLine)
+                    .map_err(|_| DebugError::Usage("back line <línea>")),
+                _ => count("back [n] | back line <línea>").map(Command::Back),
+            },
+            "reverse" => none(Command::Reverse),
+            "print" | "p" => match args.as_slice() {
+                [var] => Ok(Command::Print(var.to_string())),
+                _ => Err(DebugError::Usage("print <var>")),
+            },
+            "set" => match rest.split_once(char::is_whitespace) {
+                Some((var, value)) => Ok(Command::Set(var.to_string(), value.trim().to_string())),
+                None => Err(DebugError::Usage("set <var> <valor>")),
+            },
+            "stack" => none(Command::Stack),
+            "vars" => none(Command::Vars),
+            "list" | "l" => match args.as_slice() {
+                [] => Ok(Command::List(5)),
+                _ => number("list [n]").map(Command::List),
+            },
+            "info" => match args.as_slice() {
+                ["labels"] => Ok(Command::Info(InfoTopic::Labels)),
+                ["breakpoints" | "break" | "watchpoints"] => {
+                    Ok(Command::Info(InfoTopic::Breakpoints))
+                }
+                ["frames"] => Ok(Command::Info(InfoTopic::Frames)),
+                ["heap"] => Ok(Command::Info(InfoTopic::Heap)),
+                _ => Err(DebugError::Usage("info labels|breakpoints|frames|heap")),
+            },
+            "break" | "b" if !rest.is_empty() => Ok(Command::Break(rest.to_string())),
+            "break" | "b" => Err(DebugError::Usage("break <ubicación> [if <condición>]")),
+            "watch" => match args.as_slice() {
+                ["write", var] => Ok(Command::Watch(var.to_string(), WatchKind::Write)),
+                [var] => Ok(Command::Watch(var.to_string(), WatchKind::Change)),
+                _ => Err(DebugError::Usage("watch [write] <var>")),
+            },
+            "delete" | "d" => number("delete <n>").map(Command::Delete),
+            "gc" => none(Command::Gc),
+            "help" | "h" => none(Command::Help),
+            "quit" | "q" => none(Command::Quit),
+            _ => Err(DebugError::UnknownCommand(name.to_string())),
+        }
+    }
+}
+
+/// Nombre con el que se escribe un comando sin argumentos.
+fn name_of(command: &Command) -> &'static str {
+    match command {
+        Command::Next => "next",
+        Command::Continue => "continue",
+        Command::Reverse => "reverse",
+        Command::Stack => "stack",
+        Command::Vars => "vars",
+        Command::Gc => "gc",
+        Command::Help => "help",
+        Command::Quit => "quit",
+        _ => unreachable!("el comando lleva argumentos"),
+    }
+}
+
+/// Depurador interactivo de línea de comandos, con edición de línea e
+/// historial.
+#[derive(Debug, Default)]
+pub struct Repl {
+    debugger: Debugger,
+    /// Último comando válido, para repetirlo con una línea vacía.
+    last: Option<Command>,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Repl::default()
+    }
+
+    pub fn debugger(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /// Lee y ejecuta comandos hasta `quit` o el fin de la entrada.
+    pub fn run(&mut self, vm: &mut VM) -> rustyline::Result<()> {
+        let mut editor = DefaultEditor::new()?;
+        println!("Escriba `help` para ver los comandos.");
+        println!("Instruccion actual: {}", describe_current(vm));
+        loop {
+            let line = match editor.readline("(vm) ") {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => return Ok(()),
+                Err(error) => return Err(error),
+            };
+            let command = if line.trim().is_empty() {
+                match self.last.clone() {
+                    Some(command) => command,
+                    None => continue,
+                }
+            } else {
+                editor.add_history_entry(line.as_str())?;
+                match Command::parse(&line) {
+                    Ok(command) => command,
+                    Err(error) => {
+                        println!("{}", error);
+                        continue;
+                    }
+                }
+            };
+            self.last = Some(command.clone());
+            if !self.execute(vm, command) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Ejecuta un comando. Devuelve `false` si el comando fue `quit`.
+    pub fn execute(&mut self, vm: &mut VM, command: Command) -> bool {
+        match command {
+            Command::Step(steps) => {
+                for _ in 0..steps {
+                    match self.debugger.step(vm) {
+                        Ok(Stop::Step) => {}
+                        stop => {
+                            report(vm, stop);
+                            return true;
+                        }
+                    }
+                }
+                report(vm, Ok(Stop::Step));
+            }
+            Command::Next => {
+                let stop = self.debugger.step_over(vm);
+                report(vm, stop);
+            }
+            Command::Continue => {
+                let stop = self.debugger.resume(vm);
+                report(vm, stop);
+            }
+            Command::Back(steps) => {
+                let undone = (0..steps).take_while(|_| vm.step_back()).count();
+                self.debugger.sync(vm);
+                println!("Se deshicieron {} paso(s).", undone);
+                println!("Instruccion actual: {}", describe_current(vm));
+            }
+            Command::BackToLine(line) => {
+                let mut undone = 0;
+                while vm.step_back() {
+                    undone += 1;
+                    if vm.program().line_of(vm.ip()) == Some(line) {
+                        break;
+                    }
+                }
+                self.debugger.sync(vm);
+                if vm.program().line_of(vm.ip()) != Some(line) {
+                    println!("El historial no llega a la línea {}.", line);
+                }
+                println!("Se deshicieron {} paso(s).", undone);
+                println!("Instruccion actual: {}", describe_current(vm));
+            }
+            Command::Reverse => {
+                let stop = self.debugger.reverse(vm);
+                report(vm, Ok(stop));
+            }
+            Command::Print(var) => match vm.lookup_var(&var) {
+                Some(val) => println!("{} = {}", var, vm.heap().pretty(val)),
+                None => println!("variable {} no encontrada", var),
+            },
+            Command::Set(var, text) => match parse_literal(&text, vm.config().bigint) {
+                Ok(val) => {
+                    vm.set_var(&var, val);
+                    self.debugger.sync(vm);
+                }
+                Err(error) => println!("{}", error),
+            },
+            Command::Stack => {
+                println!("Contenido de la pila:");
+                vm.print_stack();
+            }
+            Command::Vars => {
+                println!("Contenido de las variables:");
+                vm.print_vars();
+            }
+            Command::List(context) => self.list(vm, context),
+            Command::Info(InfoTopic::Labels) => {
+                let mut labels: Vec<_> = vm.program().labels.iter().collect();
+                labels.sort_by_key(|&(name, &ix)| (ix, name));
+                for (name, &ix) in labels {
+                    match vm.program().line_of(ix) {
+                        Some(line) => println!("{:<12} instrucción {} (línea {})", name, ix, line),
+                        None => println!("{:<12} instrucción {}", name, ix),
+                    }
+                }
+            }
+            Command::Info(InfoTopic::Breakpoints) => {
+                for bp in self.debugger.breakpoints() {
+                    println!("Ruptura {}", bp);
+                }
+                for wp in self.debugger.watchpoints() {
+                    println!("Observación {}", wp);
+                }
+            }
+            Command::Info(InfoTopic::Frames) => {
+                println!("Pila de llamadas:");
+                vm.print_backtrace();
+            }
+            Command::Info(InfoTopic::Heap) => {
+                println!("Objetos del heap:");
+                vm.print_heap();
+            }
+            Command::Break(spec) => match self.debugger.add_breakpoint(vm.program(), &spec) {
+                Ok(bp) => println!("Punto de ruptura {}", bp),
+                Err(error) => println!("{}", error),
+            },
+            Command::Watch(var, kind) => {
+                let wp = self.debugger.add_watchpoint(vm, &var, kind);
+                println!("Punto de observación {}", wp);
+            }
+            Command::Delete(id) => match self.debugger.remove(id) {
+                Ok(()) => println!("Punto eliminado."),
+                Err(error) => println!("{}", error),
+            },
+            Command::Gc => {
+                let report = vm.collect_garbage();
+                println!(
+                    "Recolección terminada: {} objeto(s) liberado(s), {} vivo(s)",
+                    report.freed, report.live
+                );
+            }
+            Command::Help => println!("{}", HELP),
+            Command::Quit => return false,
+        }
+        true
+    }
+
+    /// Muestra `context` instrucciones antes y después de la actual.
+    /// `=>` marca la actual y `*` las que tienen un punto de ruptura.
+    fn list(&self, vm: &VM, context: usize) {
+        let program = vm.program();
+        let start = vm.ip().saturating_sub(context);
+        let end = (vm.ip() + context + 1).min(program.len());
+        for ix in start..end {
+            let marker = if ix == vm.ip() { "=>" } else { "  " };
+            let bp = if self
+                .debugger
+                .breakpoints()
+                .iter()
+                .any(|bp| bp.address == ix)
+            {
+                "*"
+            } else {
+                " "
+            };
+            match program.location(ix) {
+                Some(location) => println!(
+                    "{}{} {:>4} {:>4}: {}",
+                    marker, bp, ix, location.line, location.text
+                ),
+                None => println!("{}{} {:>4}: {:?}", marker, bp, ix, program.instructions[ix]),
+            }
+        }
+        if vm.ip() >= program.len() {
+            println!("=>  fin del programa");
+        }
+    }
+}
+
+fn report(vm: &VM, stop: Result<Stop, VmError>) {
+    match stop {
+        Ok(Stop::Halted) => println!("El programa terminó."),
+        Ok(Stop::Breakpoint(id)) => println!("Punto de ruptura #{} alcanzado.", id),
+        Ok(Stop::Watchpoint { id, old, new }) => {
+            let show = |val: Option<String>| val.unwrap_or("<indefinida>".to_string());
+            println!(
+                "Punto de observación #{}: {} -> {}",
+                id,
+                show(old),
+                show(new)
+            );
+        }
+        Ok(Stop::HistoryStart) => println!("Se llegó al inicio del historial."),
+        Ok(Stop::Step) => {}
+        Err(error) => {
+            println!("{}", error);
+            if let Some(location) = vm.program().location(error.ip) {
+                println!("  --> {}", location);
+            }
+        }
+    }
+    println!("Instruccion actual: {}", describe_current(vm));
+}
+
+/// Instrucción actual junto con la línea del archivo de la que proviene.
+fn describe_current(vm: &VM) -> String {
+    match (vm.current_instruction(), vm.current_location()) {
+        (Some(instr), Some(location)) => format!("[{}] {:?}  ({})", vm.ip(), instr, location),
+        (Some(instr), None) => format!("[{}] {:?}", vm.ip(), instr),
+        (None, _) => "fin del programa".to_string(),
+    }
+}
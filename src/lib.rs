@@ -1,2 +1,15 @@
+pub mod bytecode;
+#[cfg(feature = "serde")]
+pub mod dap;
+pub mod debugger;
+#[cfg(feature = "serde")]
+pub mod lsp;
 pub mod parse;
+pub mod profile;
+pub mod program;
+#[cfg(feature = "serde")]
+pub mod protocol;
+#[cfg(feature = "serde")]
+mod serde_helpers;
+pub mod trace;
 pub mod vm;
@@ -0,0 +1,426 @@
+use super::parse::{operand_kind, Parser, Symbol, SymbolKind};
+use super::protocol::{read_message, write_message};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, BufRead, Write};
+
+mod docs;
+
+// Códigos de error de JSON-RPC y del protocolo.
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REQUEST_FAILED: i64 = -32803;
+
+// Valores de `CompletionItemKind`.
+const COMPLETION_VARIABLE: u64 = 6;
+const COMPLETION_KEYWORD: u64 = 14;
+const COMPLETION_REFERENCE: u64 = 18;
+
+type RequestResult = Result<Value, (i64, String)>;
+
+/// Atiende un cliente del Language Server Protocol hasta que envía `exit`
+/// o se acaba la entrada. Devuelve `true` si el cliente pidió `shutdown`
+/// antes de salir, como indica el protocolo para terminar sin error.
+pub fn serve<R: BufRead, W: Write>(mut input: R, output: W) -> io::Result<bool> {
+    let mut server = Server {
+        output,
+        documents: HashMap::new(),
+        shutdown: false,
+    };
+    loop {
+        let message = match read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) => return Ok(false),
+            // Un mensaje mal formado no termina la sesión: se contesta sin
+            // `id`, porque no se pudo leer, y se sigue con el siguiente.
+            Err(error) if error.kind() == io::ErrorKind::InvalidData => {
+                let error = (PARSE_ERROR, format!("mensaje inválido: {}", error));
+                server.respond(&Value::Null, Err(error))?;
+                continue;
+            }
+            Err(error) => return Err(error),
+        };
+        let method = message["method"].as_str().unwrap_or_default();
+        if method == "exit" {
+            return Ok(server.shutdown);
+        }
+        match message.get("id") {
+            // Respuesta del cliente; el servidor no le hace peticiones.
+            Some(_) if method.is_empty() => {}
+            Some(id) => {
+                let result = server.request(method, &message["params"]);
+                server.respond(id, result)?;
+            }
+            None => server.notification(method, &message["params"])?,
+        }
+    }
+}
+
+struct Server<W> {
+    output: W,
+    /// Texto de cada documento abierto, por URI.
+    documents: HashMap<String, String>,
+    shutdown: bool,
+}
+
+impl<W: Write> Server<W> {
+    fn respond(&mut self, id: &Value, result: RequestResult) -> io::Result<()> {
+        let mut response = json!({ "jsonrpc": "2.0", "id": id });
+        match result {
+            Ok(value) => response["result"] = value,
+            Err((code, message)) => response["error"] = json!({ "code": code, "message": message }),
+        }
+        write_message(&mut self.output, &response)
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> io::Result<()> {
+        let message = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        write_message(&mut self.output, &message)
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> io::Result<()> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str().unwrap_or_default();
+                self.documents.insert(uri.to_string(), text.to_string());
+                self.publish_diagnostics(uri)
+            }
+            // Se anuncia sincronización completa: el último cambio trae el
+            // documento entero.
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                if let Some(text) = changes
+                    .and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str())
+                {
+                    self.documents.insert(uri.to_string(), text.to_string());
+                }
+                self.publish_diagnostics(uri)
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                self.notify(
+                    "textDocument/publishDiagnostics",
+                    json!({ "uri": uri, "diagnostics": [] }),
+                )
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// No publica nada si el documento no está abierto, como pasa con un
+    /// `didChange` sin texto para un URI desconocido.
+    fn publish_diagnostics(&mut self, uri: &str) -> io::Result<()> {
+        let Some(text) = self.documents.get(uri) else {
+            return Ok(());
+        };
+        let errors = Parser::with_file(uri)
+            .parse_file(text)
+            .err()
+            .unwrap_or_default();
+        let diagnostics: Vec<Value> = errors
+            .iter()
+            .map(|error| {
+                json!({
+                    "range": range(text, error.line, &error.columns),
+                    "severity": 1,
+                    "source": "vainilla-machine",
+                    "message": error.message(),
+                })
+            })
+            .collect();
+        self.notify(
+            "textDocument/publishDiagnostics",
+            json!({ "uri": uri, "diagnostics": diagnostics }),
+        )
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> RequestResult {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": {},
+                    "renameProvider": { "prepareProvider": true },
+                },
+                "serverInfo": {
+                    "name": "vainilla-machine",
+                    "version": env!("CARGO_PKG_VERSION"),
+                },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => {
+                let (doc, symbol) = self.symbol_at(params)?;
+                let Some(label) = symbol.filter(is_label) else {
+                    return Ok(Value::Null);
+                };
+                let definition = doc.definition(&label.name);
+                Ok(definition.map_or(Value::Null, |symbol| doc.location(symbol)))
+            }
+            "textDocument/references" => {
+                let (doc, symbol) = self.symbol_at(params)?;
+                let Some(label) = symbol.filter(is_label) else {
+                    return Ok(json!([]));
+                };
+                let declaration = params["context"]["includeDeclaration"]
+                    .as_bool()
+                    .unwrap_or(true);
+                let locations: Vec<Value> = doc
+                    .label_occurrences(&label.name)
+                    .filter(|symbol| declaration || symbol.kind != SymbolKind::LabelDefinition)
+                    .map(|symbol| doc.location(symbol))
+                    .collect();
+                Ok(json!(locations))
+            }
+            "textDocument/hover" => {
+                let (doc, symbol) = self.symbol_at(params)?;
+                let Some(symbol) = symbol else {
+                    return Ok(Value::Null);
+                };
+                let markdown = match symbol.kind {
+                    SymbolKind::Instruction => match docs::lookup(&symbol.name) {
+                        Some(doc) => doc.markdown(),
+                        None => return Ok(Value::Null),
+                    },
+                    SymbolKind::LabelDefinition | SymbolKind::LabelReference => {
+                        match doc.definition(&symbol.name) {
+                            Some(definition) => format!(
+                                "Etiqueta `{}`, definida en la línea {}",
+                                symbol.name, definition.line
+                            ),
+                            None => format!("Etiqueta `{}` sin definir", symbol.name),
+                        }
+                    }
+                    SymbolKind::Variable => format!("Variable `{}`", symbol.name),
+                };
+                Ok(json!({
+                    "contents": { "kind": "markdown", "value": markdown },
+                    "range": range(doc.text, symbol.line, &symbol.columns),
+                }))
+            }
+            "textDocument/completion" => self.completion(params),
+            "textDocument/prepareRename" => {
+                let (doc, symbol) = self.symbol_at(params)?;
+                Ok(match symbol.filter(is_label) {
+                    Some(label) => json!({
+                        "range": range(doc.text, label.line, &label.columns),
+                        "placeholder": label.name,
+                    }),
+                    None => Value::Null,
+                })
+            }
+            "textDocument/rename" => self.rename(params),
+            _ => Err((METHOD_NOT_FOUND, format!("método no soportado: {}", method))),
+        }
+    }
+
+    /// Documento de la petición y el símbolo que está bajo el cursor.
+    fn symbol_at(&self, params: &Value) -> Result<(Document<'_>, Option<Symbol>), (i64, String)> {
+        let doc = self.document(params)?;
+        let (line, column) = doc.cursor(params)?;
+        let symbol = doc
+            .symbols
+            .iter()
+            .find(|symbol| {
+                symbol.line == line
+                    && symbol.columns.start <= column
+                    && column <= symbol.columns.end
+            })
+            .cloned();
+        Ok((doc, symbol))
+    }
+
+    fn document(&self, params: &Value) -> Result<Document<'_>, (i64, String)> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        match self.documents.get(uri) {
+            Some(text) => Ok(Document {
+                uri: uri.to_string(),
+                text,
+                symbols: Parser::symbols(text),
+            }),
+            None => Err((INVALID_PARAMS, format!("documento no abierto: {}", uri))),
+        }
+    }
+
+    /// Al inicio de la línea se completan instrucciones; como operando,
+    /// etiquetas o variables según la instrucción.
+    fn completion(&self, params: &Value) -> RequestResult {
+        let doc = self.document(params)?;
+        let (line, column) = doc.cursor(params)?;
+        let text = doc.text.lines().nth(line - 1).unwrap_or_default();
+        let before: String = text.chars().take(column - 1).collect();
+        if before.contains(';') || before.contains('"') {
+            return Ok(json!([]));
+        }
+        let tokens: Vec<&str> = before.split_whitespace().collect();
+        let typing = match before.ends_with(char::is_whitespace) || before.is_empty() {
+            true => tokens.len(),
+            false => tokens.len() - 1,
+        };
+        let items: Vec<Value> = match typing {
+            0 => docs::DOCS
+                .iter()
+                .map(|doc| {
+                    json!({
+                        "label": doc.mnemonic,
+                        "kind": COMPLETION_KEYWORD,
+                        "detail": doc.effect,
+                        "documentation": doc.summary,
+                    })
+                })
+                .collect(),
+            1 => {
+                let (kind, item_kind) = match operand_kind(tokens[0]) {
+                    Some(SymbolKind::LabelReference) => {
+                        (SymbolKind::LabelDefinition, COMPLETION_REFERENCE)
+                    }
+                    Some(kind) => (kind, COMPLETION_VARIABLE),
+                    None => return Ok(json!([])),
+                };
+                let names: BTreeSet<&str> = doc
+                    .symbols
+                    .iter()
+                    .filter(|symbol| symbol.kind == kind && symbol.line != line)
+                    .map(|symbol| symbol.name.as_str())
+                    .collect();
+                names
+                    .into_iter()
+                    .map(|name| json!({ "label": name, "kind": item_kind }))
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        Ok(json!(items))
+    }
+
+    fn rename(&self, params: &Value) -> RequestResult {
+        let (doc, symbol) = self.symbol_at(params)?;
+        let Some(label) = symbol.filter(is_label) else {
+            return Err((
+                REQUEST_FAILED,
+                "solo se pueden renombrar etiquetas".to_string(),
+            ));
+        };
+        let new_name = params["newName"].as_str().unwrap_or_default();
+        if new_name.is_empty()
+            || new_name.contains(|c: char| c.is_whitespace() || matches!(c, ':' | ';' | '"'))
+        {
+            return Err((
+                INVALID_PARAMS,
+                format!("nombre de etiqueta inválido: {:?}", new_name),
+            ));
+        }
+        if new_name != label.name && doc.label_occurrences(new_name).next().is_some() {
+            return Err((
+                REQUEST_FAILED,
+                format!("la etiqueta {} ya existe", new_name),
+            ));
+        }
+        let edits: Vec<Value> = doc
+            .label_occurrences(&label.name)
+            .map(|symbol| {
+                json!({
+                    "range": range(doc.text, symbol.line, &symbol.columns),
+                    "newText": new_name,
+                })
+            })
+            .collect();
+        let mut changes = serde_json::Map::new();
+        changes.insert(doc.uri, json!(edits));
+        Ok(json!({ "changes": changes }))
+    }
+}
+
+fn is_label(symbol: &Symbol) -> bool {
+    matches!(
+        symbol.kind,
+        SymbolKind::LabelDefinition | SymbolKind::LabelReference
+    )
+}
+
+/// Documento abierto junto con los nombres que aparecen en él.
+struct Document<'a> {
+    uri: String,
+    text: &'a str,
+    symbols: Vec<Symbol>,
+}
+
+impl Document<'_> {
+    /// Línea y columna (base 1, en caracteres) de la posición de la
+    /// petición. Una columna más allá del final de la línea se recorta; una
+    /// línea fuera del documento es un error.
+    fn cursor(&self, params: &Value) -> Result<(usize, usize), (i64, String)> {
+        let position = &params["position"];
+        let line = position["line"].as_u64().unwrap_or_default();
+        let character = position["character"].as_u64().unwrap_or_default();
+        // El cursor puede estar en la línea vacía después del último salto.
+        let lines = self.text.lines().count();
+        let line = match usize::try_from(line) {
+            Ok(line) if line <= lines => line + 1,
+            _ => {
+                return Err((
+                    INVALID_PARAMS,
+                    format!("posición fuera del documento: línea {}", line),
+                ))
+            }
+        };
+        let character = usize::try_from(character).unwrap_or(usize::MAX);
+        let text = self.text.lines().nth(line - 1).unwrap_or_default();
+        Ok((line, char_column(text, character)))
+    }
+
+    fn definition(&self, name: &str) -> Option<&Symbol> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.kind == SymbolKind::LabelDefinition && symbol.name == name)
+    }
+
+    fn label_occurrences<'s>(&'s self, name: &'s str) -> impl Iterator<Item = &'s Symbol> {
+        self.symbols
+            .iter()
+            .filter(move |symbol| is_label(symbol) && symbol.name == name)
+    }
+
+    fn location(&self, symbol: &Symbol) -> Value {
+        json!({
+            "uri": self.uri,
+            "range": range(self.text, symbol.line, &symbol.columns),
+        })
+    }
+}
+
+/// Rango LSP (base 0, columnas en unidades UTF-16) de las columnas
+/// `columns` (base 1, en caracteres) de la línea `line`.
+fn range(text: &str, line: usize, columns: &std::ops::Range<usize>) -> Value {
+    let line_text = text.lines().nth(line - 1).unwrap_or_default();
+    let position = |column: usize| {
+        let character: usize = line_text
+            .chars()
+            .take(column - 1)
+            .map(char::len_utf16)
+            .sum();
+        json!({ "line": line - 1, "character": character })
+    };
+    json!({ "start": position(columns.start), "end": position(columns.end) })
+}
+
+/// Columna en caracteres (base 1) que corresponde a `character` unidades
+/// UTF-16 desde el inicio de la línea.
+fn char_column(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (ix, c) in line.chars().enumerate() {
+        if units >= character {
+            return ix + 1;
+        }
+        units += c.len_utf16();
+    }
+    line.chars().count() + 1
+}
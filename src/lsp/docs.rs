@@ -0,0 +1,239 @@
+/// Documentación de una instrucción para mostrarla en el editor.
+pub(super) struct Doc {
+    pub mnemonic: &'static str,
+    pub operand: Option<&'static str>,
+    /// Efecto en la pila, en la notación `( antes -- después )`.
+    pub effect: &'static str,
+    pub summary: &'static str,
+}
+
+const fn doc(
+    mnemonic: &'static str,
+    operand: Option<&'static str>,
+    effect: &'static str,
+    summary: &'static str,
+) -> Doc {
+    Doc {
+        mnemonic,
+        operand,
+        effect,
+        summary,
+    }
+}
+
+const LABEL: Option<&str> = Some("etiqueta");
+const VAR: Option<&str> = Some("variable");
+
+pub(super) const DOCS: &[Doc] = &[
+    doc(
+        "LOAD_CONST",
+        Some("valor"),
+        "( -- v )",
+        "Apila una constante: un número, `true`, `false` o una cadena entre comillas.",
+    ),
+    doc(
+        "LOAD_VAR",
+        VAR,
+        "( -- v )",
+        "Apila el valor de la variable. Dentro de una subrutina busca primero entre sus locales y después entre las globales.",
+    ),
+    doc(
+        "STORE_VAR",
+        VAR,
+        "( v -- )",
+        "Guarda el tope en la variable. Dentro de una subrutina escribe una local.",
+    ),
+    doc(
+        "LOAD_GLOBAL",
+        VAR,
+        "( -- v )",
+        "Apila el valor de la variable global, aunque haya una local con el mismo nombre.",
+    ),
+    doc(
+        "STORE_GLOBAL",
+        VAR,
+        "( v -- )",
+        "Guarda el tope en la variable global, también desde una subrutina.",
+    ),
+    doc("ADD", None, "( a b -- a+b )", "Suma dos números."),
+    doc("SUB", None, "( a b -- a-b )", "Resta dos números."),
+    doc("MUL", None, "( a b -- a*b )", "Multiplica dos números."),
+    doc(
+        "DIV",
+        None,
+        "( a b -- a/b )",
+        "Divide dos números. Entre enteros da un flotante, o una fracción exacta con `--rational`.",
+    ),
+    doc(
+        "IDIV",
+        None,
+        "( a b -- q )",
+        "División entera, truncando hacia cero.",
+    ),
+    doc("POW", None, "( a b -- a^b )", "Eleva `a` a la potencia `b`."),
+    doc("MOD", None, "( a b -- r )", "Residuo de dividir `a` entre `b`."),
+    doc(
+        "PRINT",
+        None,
+        "( v -- )",
+        "Escribe el tope en la salida.",
+    ),
+    doc(
+        "READ",
+        None,
+        "( -- v )",
+        "Lee una línea de la entrada. Si es un número se apila como número; si no, como cadena.",
+    ),
+    doc("JMP", LABEL, "( -- )", "Salta a la etiqueta."),
+    doc("JMPEQ", LABEL, "( n -- )", "Salta si el tope es igual a cero."),
+    doc("JMPNE", LABEL, "( n -- )", "Salta si el tope es distinto de cero."),
+    doc("JMPGT", LABEL, "( n -- )", "Salta si el tope es mayor que cero."),
+    doc("JMPLT", LABEL, "( n -- )", "Salta si el tope es menor que cero."),
+    doc("JMPGE", LABEL, "( n -- )", "Salta si el tope es mayor o igual a cero."),
+    doc("JMPLE", LABEL, "( n -- )", "Salta si el tope es menor o igual a cero."),
+    doc("JMPT", LABEL, "( b -- )", "Salta si el tope es verdadero."),
+    doc("JMPF", LABEL, "( b -- )", "Salta si el tope es falso."),
+    doc("DUP", None, "( a -- a a )", "Duplica el tope."),
+    doc("POP", None, "( a -- )", "Descarta el tope."),
+    doc("SWAP", None, "( a b -- b a )", "Intercambia los dos valores del tope."),
+    doc("OVER", None, "( a b -- a b a )", "Copia al tope el segundo valor."),
+    doc("ROT", None, "( a b c -- b c a )", "Rota los tres valores del tope."),
+    doc(
+        "PICK",
+        Some("n"),
+        "( vn ... v0 -- vn ... v0 vn )",
+        "Copia al tope el valor que está `n` posiciones debajo de él. `PICK 0` equivale a `DUP`.",
+    ),
+    doc(
+        "EQ",
+        None,
+        "( a b -- bool )",
+        "Compara si son iguales; los arreglos y mapas se comparan por contenido.",
+    ),
+    doc("NE", None, "( a b -- bool )", "Compara si son distintos."),
+    doc("LT", None, "( a b -- bool )", "Compara si `a` es menor que `b`."),
+    doc("LE", None, "( a b -- bool )", "Compara si `a` es menor o igual a `b`."),
+    doc("GT", None, "( a b -- bool )", "Compara si `a` es mayor que `b`."),
+    doc("GE", None, "( a b -- bool )", "Compara si `a` es mayor o igual a `b`."),
+    doc("AND", None, "( a b -- bool )", "Conjunción lógica."),
+    doc("OR", None, "( a b -- bool )", "Disyunción lógica."),
+    doc("XOR", None, "( a b -- bool )", "Disyunción exclusiva."),
+    doc("NOT", None, "( a -- bool )", "Negación lógica."),
+    doc("CONCAT", None, "( a b -- ab )", "Concatena dos cadenas."),
+    doc("LEN", None, "( s -- n )", "Longitud de la cadena en caracteres."),
+    doc(
+        "SUBSTR",
+        None,
+        "( s inicio longitud -- sub )",
+        "Subcadena de `s`, contando caracteres.",
+    ),
+    doc("TO_STR", None, "( v -- s )", "Convierte el valor en cadena."),
+    doc(
+        "TO_NUM",
+        None,
+        "( s -- n )",
+        "Convierte una cadena en número; un número se queda igual.",
+    ),
+    doc("TO_FLOAT", None, "( n -- f )", "Convierte el número en flotante."),
+    doc(
+        "NUM",
+        None,
+        "( r -- n )",
+        "Numerador de una fracción; un entero se queda igual.",
+    ),
+    doc(
+        "DEN",
+        None,
+        "( r -- n )",
+        "Denominador de una fracción; el de un entero es 1.",
+    ),
+    doc(
+        "NEW_ARRAY",
+        Some("n"),
+        "( v0 ... vn-1 -- arreglo )",
+        "Crea un arreglo con los `n` valores del tope.",
+    ),
+    doc(
+        "ARRAY_GET",
+        None,
+        "( arreglo índice -- valor )",
+        "Elemento del arreglo en el índice.",
+    ),
+    doc(
+        "ARRAY_SET",
+        None,
+        "( arreglo índice valor -- arreglo )",
+        "Cambia el elemento del índice; modifica el mismo arreglo.",
+    ),
+    doc("ARRAY_LEN", None, "( arreglo -- n )", "Número de elementos."),
+    doc(
+        "ARRAY_PUSH",
+        None,
+        "( arreglo valor -- arreglo )",
+        "Agrega el valor al final; modifica el mismo arreglo.",
+    ),
+    doc(
+        "ARRAY_POP",
+        None,
+        "( arreglo -- arreglo valor )",
+        "Quita el último elemento; modifica el mismo arreglo.",
+    ),
+    doc("NEW_MAP", None, "( -- mapa )", "Crea un mapa vacío."),
+    doc(
+        "MAP_GET",
+        None,
+        "( mapa llave -- valor )",
+        "Valor de la llave; es un error si no existe.",
+    ),
+    doc(
+        "MAP_SET",
+        None,
+        "( mapa llave valor -- mapa )",
+        "Asigna el valor a la llave; modifica el mismo mapa.",
+    ),
+    doc(
+        "MAP_HAS",
+        None,
+        "( mapa llave -- bool )",
+        "Indica si la llave existe.",
+    ),
+    doc(
+        "MAP_DEL",
+        None,
+        "( mapa llave -- mapa )",
+        "Borra la llave si existe; modifica el mismo mapa.",
+    ),
+    doc(
+        "MAP_KEYS",
+        None,
+        "( mapa -- arreglo )",
+        "Arreglo nuevo con las llaves en orden.",
+    ),
+    doc(
+        "CALL",
+        LABEL,
+        "( -- )",
+        "Llama a la subrutina: crea un marco para sus variables locales y salta a la etiqueta.",
+    ),
+    doc(
+        "RET",
+        None,
+        "( -- )",
+        "Regresa a la instrucción siguiente al `CALL` y descarta las locales de la subrutina.",
+    ),
+];
+
+pub(super) fn lookup(mnemonic: &str) -> Option<&'static Doc> {
+    DOCS.iter().find(|doc| doc.mnemonic == mnemonic)
+}
+
+impl Doc {
+    /// Texto en Markdown para `hover`.
+    pub fn markdown(&self) -> String {
+        let usage = match self.operand {
+            Some(operand) => format!("{} <{}>", self.mnemonic, operand),
+            None => self.mnemonic.to_string(),
+        };
+        format!("```\n{}  {}\n```\n{}", usage, self.effect, self.summary)
+    }
+}
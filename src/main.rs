@@ -1,7 +1,12 @@
-use clap::{Args, Parser as CParser, Subcommand};
+use clap::{Args, Parser as CParser, Subcommand, ValueEnum};
 use std::fs;
-use std::io::Read;
+use std::io::{BufReader, BufWriter, Read, Write};
+use vainilla_machine::bytecode;
+use vainilla_machine::debugger::Repl;
 use vainilla_machine::parse;
+use vainilla_machine::profile;
+use vainilla_machine::program::Program;
+use vainilla_machine::trace;
 use vainilla_machine::vm;
 
 #[derive(CParser)]
@@ -13,6 +18,27 @@ struct Cli {
     #[arg(short, long)]
     /// Turn on/off processing output
     debug: bool,
+    #[arg(long)]
+    /// Maximum number of nested CALLs before aborting [default: 1024]
+    max_call_depth: Option<usize>,
+    #[arg(long)]
+    /// Treat non-zero numbers as true in logical operations and JMPT/JMPF
+    truthy: bool,
+    #[arg(long)]
+    /// Integer overflow behavior: error, wrap, saturate or promote [default: error]
+    overflow: Option<vm::OverflowMode>,
+    #[arg(long)]
+    /// Promote integers that overflow i64 to arbitrary precision
+    bigint: bool,
+    #[arg(long)]
+    /// Make DIV between integers produce exact fractions
+    rational: bool,
+    #[arg(long)]
+    /// Maximum number of live arrays and maps on the heap [default: 1048576]
+    heap_limit: Option<usize>,
+    #[arg(long, default_value_t = 10_000)]
+    /// Number of steps the debugger can undo
+    history_limit: usize,
     // #[arg(short, long)]
 }
 
@@ -20,14 +46,296 @@ struct Cli {
 enum Commands {
     /// Scan files and put output on the same dir with the same name of the file but .lex appended
     Run(RunArgs),
-    RunStdin,
+    RunStdin(ExecArgs),
     /// Scan files and put output on the same dir with the same name of the file but .lex appended
-    Parse(RunArgs),
+    Parse(ParseArgs),
+    /// Assemble a .vm file into binary bytecode (.vmb)
+    Assemble(AssembleArgs),
+    /// Print a .vmb file back as assembler text
+    Disassemble(FileArgs),
+    /// Continue an execution saved with --save-on-exit
+    #[cfg(feature = "serde")]
+    Resume(ResumeArgs),
+    /// Serve the Debug Adapter Protocol over stdin/stdout
+    #[cfg(feature = "serde")]
+    Dap,
+    /// Serve the Language Server Protocol for .vm files over stdin/stdout
+    #[cfg(feature = "serde")]
+    Lsp,
+}
+
+#[derive(Args, Clone)]
+struct FileArgs {
+    file: String,
 }
 
 #[derive(Args, Clone)]
 struct RunArgs {
     file: String,
+    #[command(flatten)]
+    exec: ExecArgs,
+}
+
+#[derive(Args, Clone)]
+struct ExecArgs {
+    #[cfg(feature = "serde")]
+    #[arg(long, value_name = "STATE_FILE")]
+    /// Save the VM state as JSON when execution stops, to continue it later with `resume`
+    save_on_exit: Option<String>,
+    #[arg(long)]
+    /// Stop after executing this many instructions (ignored with --debug)
+    max_steps: Option<u64>,
+    #[arg(long, value_name = "FILE")]
+    /// Read the program's READ input from this file instead of stdin
+    input: Option<String>,
+    #[arg(long)]
+    /// Log every executed instruction with the stack before and after it (ignored with --debug)
+    trace: bool,
+    #[arg(long, default_value_t = trace::TraceFormat::default())]
+    /// Trace format: text, or json for one JSON object per line
+    trace_format: trace::TraceFormat,
+    #[arg(long, value_name = "FILE")]
+    /// Write the trace to this file instead of stderr
+    trace_file: Option<String>,
+    #[arg(long, value_name = "LABEL")]
+    /// Only trace instructions from this label on
+    trace_from: Option<String>,
+    #[arg(long, value_name = "LABEL")]
+    /// Only trace instructions before this label
+    trace_to: Option<String>,
+    #[arg(long)]
+    /// Count executions per instruction and per labeled block and print a hot-spot report (ignored with --debug)
+    profile: bool,
+    #[arg(long, default_value_t = 10, value_name = "N")]
+    /// Number of instructions listed in the profile report
+    profile_top: usize,
+    #[arg(long, value_name = "FILE")]
+    /// Also write the profile as collapsed stacks, the input format of flamegraph tools
+    profile_collapsed: Option<String>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Args, Clone)]
+struct ResumeArgs {
+    /// State file written by --save-on-exit
+    state: String,
+    #[command(flatten)]
+    exec: ExecArgs,
+}
+
+#[derive(Args, Clone)]
+struct ParseArgs {
+    file: String,
+    #[arg(long, value_enum, default_value_t = Format::Debug)]
+    /// Output format for the parsed program
+    format: Format,
+}
+
+#[derive(ValueEnum, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// One instruction per line, as Rust debug output
+    Debug,
+    /// The whole program (instructions, labels and source map) as JSON
+    #[cfg(feature = "serde")]
+    Json,
+}
+
+#[derive(Args, Clone)]
+struct AssembleArgs {
+    file: String,
+    #[arg(short, long)]
+    /// Output file (defaults to the input name with a .vmb extension)
+    output: Option<String>,
+    #[arg(long)]
+    /// Leave out the source map and labels
+    strip: bool,
+}
+
+/// Ensambla el programa o muestra todos los errores y termina el proceso.
+fn parse_program(mut parser: parse::Parser, contents: &str) -> Program {
+    match parser.parse_file(contents) {
+        Ok(program) => program,
+        Err(errors) => {
+            for error in &errors {
+                eprintln!("{}", error.render());
+            }
+            eprintln!("{} error(es) al ensamblar el programa", errors.len());
+            std::process::exit(1);
+        }
+    }
+}
+
+fn report_error(vm: &vm::VM, error: &vm::VmError) {
+    eprintln!("{}", error);
+    if let Some(location) = vm.program().location(error.ip) {
+        eprintln!("  --> {}", location);
+    }
+}
+
+/// Aplica las opciones de la línea de comandos sobre `base`: la
+/// configuración por omisión al ejecutar o la guardada al continuar. Solo
+/// cambia lo que se pidió explícitamente.
+fn config(cli: &Cli, base: vm::VmConfig) -> vm::VmConfig {
+    vm::VmConfig {
+        max_call_depth: cli.max_call_depth.unwrap_or(base.max_call_depth),
+        truthy: base.truthy || cli.truthy,
+        overflow: cli.overflow.unwrap_or(base.overflow),
+        bigint: base.bigint || cli.bigint,
+        rational: base.rational || cli.rational,
+        heap_limit: cli.heap_limit.unwrap_or(base.heap_limit),
+        // Solo el depurador puede retroceder; fuera de él no vale la pena
+        // pagar por el registro.
+        history_limit: if cli.debug { cli.history_limit } else { 0 },
+    }
+}
+
+#[cfg(feature = "serde")]
+fn save_state(vm: &vm::VM, file_name: &str) {
+    let json =
+        serde_json::to_string(&vm.snapshot()).expect("Something went wrong serializing the state");
+    fs::write(file_name, json).expect("Something went wrong writing the state file");
+    println!("Estado guardado en {}", file_name);
+}
+
+#[cfg(feature = "serde")]
+fn load_state(file_name: &str) -> vm::Snapshot {
+    let json = fs::read_to_string(file_name).expect("Something went wrong reading the file");
+    match serde_json::from_str(&json) {
+        Ok(snapshot) => snapshot,
+        Err(error) => {
+            eprintln!("{}: estado inválido: {}", file_name, error);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Guarda el estado si se pidió con `--save-on-exit`.
+fn on_exit(vm: &vm::VM, exec: &ExecArgs) {
+    #[cfg(feature = "serde")]
+    if let Some(file_name) = &exec.save_on_exit {
+        save_state(vm, file_name);
+    }
+    #[cfg(not(feature = "serde"))]
+    let _ = (vm, exec);
+}
+
+/// Prepara la traza pedida con `--trace`.
+fn tracer(vm: &vm::VM, exec: &ExecArgs) -> trace::Tracer<Box<dyn Write>> {
+    let out: Box<dyn Write> = match &exec.trace_file {
+        Some(file_name) => Box::new(BufWriter::new(
+            fs::File::create(file_name).expect("Something went wrong creating the trace file"),
+        )),
+        None => Box::new(std::io::stderr()),
+    };
+    let mut tracer = trace::Tracer::new(out, exec.trace_format);
+    if exec.trace_from.is_some() || exec.trace_to.is_some() {
+        let range = trace::label_range(
+            vm.program(),
+            exec.trace_from.as_deref(),
+            exec.trace_to.as_deref(),
+        );
+        match range {
+            Ok(range) => tracer.set_range(range),
+            Err(error) => {
+                eprintln!("Error: {}", error);
+                std::process::exit(1);
+            }
+        }
+    }
+    tracer
+}
+
+/// Como `VM::run_steps`, pero pasando cada instrucción por la traza y el
+/// perfilador que se hayan pedido. El reporte del perfil se escribe al
+/// final, aunque haya habido un error.
+fn instrumented_run(vm: &mut vm::VM, exec: &ExecArgs) -> Result<vm::StepOutcome, vm::VmError> {
+    let mut tracer = exec.trace.then(|| tracer(vm, exec));
+    let mut profiler = exec.profile.then(|| profile::Profiler::new(vm.program()));
+    let mut taken = 0;
+    let result = loop {
+        if exec.max_steps.is_some_and(|steps| taken >= steps) {
+            break Ok(match vm.current_instruction() {
+                Some(_) => vm::StepOutcome::Continue,
+                None => vm::StepOutcome::Halted,
+            });
+        }
+        if let Some(profiler) = &mut profiler {
+            profiler.record(vm);
+        }
+        let step = match &mut tracer {
+            Some(tracer) => tracer.step(vm),
+            None => vm.step(),
+        };
+        match step {
+            Ok(vm::StepOutcome::Continue) => taken += 1,
+            other => break other,
+        }
+    };
+    if let Some(mut tracer) = tracer {
+        if let Err(error) = tracer.flush() {
+            eprintln!("Error al escribir la traza: {}", error);
+        }
+    }
+    if let Some(mut profiler) = profiler {
+        profiler.finish(vm);
+        let mut stderr = std::io::stderr();
+        if let Err(error) = profiler.report(vm.program(), exec.profile_top, &mut stderr) {
+            eprintln!("Error al escribir el perfil: {}", error);
+        }
+        if let Some(file_name) = &exec.profile_collapsed {
+            let mut out = BufWriter::new(
+                fs::File::create(file_name)
+                    .expect("Something went wrong creating the profile file"),
+            );
+            profiler
+                .write_collapsed(vm.program(), &mut out)
+                .and_then(|_| out.flush())
+                .expect("Something went wrong writing the profile file");
+        }
+    }
+    result
+}
+
+/// Ejecuta el programa, de forma interactiva si se pidió depuración.
+/// Termina el proceso con código distinto de cero si hubo un error.
+fn execute(mut vm: vm::VM, cli: &Cli, exec: &ExecArgs) {
+    if let Some(file_name) = &exec.input {
+        let file = fs::File::open(file_name).expect("Something went wrong reading the input file");
+        vm.set_input(BufReader::new(file));
+    }
+    if cli.debug {
+        println!("Ejecutando programa en modo depuración...");
+        if let Err(error) = Repl::new().run(&mut vm) {
+            eprintln!("Error en la terminal del depurador: {}", error);
+        }
+        on_exit(&vm, exec);
+    } else {
+        println!("Ejecutando programa...");
+        let result = if exec.trace || exec.profile {
+            instrumented_run(&mut vm, exec)
+        } else {
+            match exec.max_steps {
+                Some(steps) => vm.run_steps(steps),
+                None => vm.run(),
+            }
+        };
+        match result {
+            Ok(vm::StepOutcome::Halted) => on_exit(&vm, exec),
+            Ok(vm::StepOutcome::Continue) => {
+                println!(
+                    "Ejecución detenida en la instrucción {} tras {} paso(s)",
+                    vm.ip(),
+                    exec.max_steps.unwrap_or_default()
+                );
+                on_exit(&vm, exec);
+            }
+            Err(error) => {
+                report_error(&vm, &error);
+                on_exit(&vm, exec);
+                std::process::exit(1);
+            }
+        }
+    }
 }
 
 fn main() {
@@ -35,7 +343,53 @@ fn main() {
 
     match &cli.command {
         Commands::Run(run_args) => {
-            let file_name = &run_args.file;
+            let program = match bytecode::load(&run_args.file) {
+                Ok(program) => program,
+                Err(error) => {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                }
+            };
+            execute(
+                vm::VM::with_config(program, config(&cli, vm::VmConfig::default())),
+                &cli,
+                &run_args.exec,
+            );
+        }
+        #[cfg(feature = "serde")]
+        Commands::Resume(args) => {
+            let mut snapshot = load_state(&args.state);
+            snapshot.config = config(&cli, snapshot.config);
+            match vm::VM::from_snapshot(snapshot) {
+                Ok(vm) => execute(vm, &cli, &args.exec),
+                Err(error) => {
+                    eprintln!("{}: estado inválido: {}", args.state, error);
+                    std::process::exit(1);
+                }
+            }
+        }
+        #[cfg(feature = "serde")]
+        Commands::Dap => {
+            let stdin = std::io::BufReader::new(std::io::stdin());
+            if let Err(error) = vainilla_machine::dap::serve(stdin, std::io::stdout()) {
+                eprintln!("Error en la sesión de depuración: {}", error);
+                std::process::exit(1);
+            }
+        }
+        #[cfg(feature = "serde")]
+        Commands::Lsp => {
+            let stdin = std::io::stdin().lock();
+            match vainilla_machine::lsp::serve(stdin, std::io::stdout()) {
+                Ok(true) => {}
+                Ok(false) => std::process::exit(1),
+                Err(error) => {
+                    eprintln!("Error en el servidor de lenguaje: {}", error);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Commands::Assemble(args) => {
+            let file_name = &args.file;
             if !file_name.ends_with(".vm") {
                 eprintln!("Error: The input file must have a .vm extension");
                 std::process::exit(1);
@@ -43,54 +397,28 @@ fn main() {
             let contents =
                 fs::read_to_string(file_name).expect("Something went wrong reading the file");
 
-            let mut parser = parse::Parser::new();
-            let instructions = parser.parse_file(&contents);
-            let mut vm = vm::VM::new(instructions);
-
-            if cli.debug {
-                println!("Ejecutando programa en modo depuración...");
-                loop {
-                    println!("Seleccione una opción:");
-                    println!("1. Ejecutar una instrucción");
-                    println!("2. Ejecutar todo el programa");
-                    println!("3. Ver contenido de la pila");
-                    println!("4. Ver contenido de las variables");
-                    println!("5. Salir");
-
-                    let mut choice = String::new();
-                    std::io::stdin()
-                        .read_line(&mut choice)
-                        .expect("Failed to read line");
-                    let choice = choice.trim();
-
-                    match choice {
-                        "1" => {
-                            println!("Instruccion actual: {:?}", vm.current_instruction());
-                            vm.step();
-                            println!("Instruccion siguiente: {:?}", vm.current_instruction());
-                        }
-                        "2" => {
-                            vm.run();
-                        }
-                        "3" => {
-                            println!("Contenido de la pila:");
-                            vm.print_stack();
-                        }
-                        "4" => {
-                            println!("Contenido de las variables:");
-                            vm.print_vars();
-                        }
-                        "5" => {
-                            break;
-                        }
-                        _ => {
-                            println!("Opción no válida, por favor intente de nuevo.");
-                        }
-                    }
+            let program = parse_program(parse::Parser::with_file(file_name), &contents);
+            let output = match &args.output {
+                Some(output) => output.clone(),
+                None => format!("{}b", file_name),
+            };
+            let bytes = match bytecode::encode(&program, !args.strip) {
+                Ok(bytes) => bytes,
+                Err(error) => {
+                    eprintln!("{}: {}", file_name, error);
+                    std::process::exit(1);
+                }
+            };
+            fs::write(&output, bytes).expect("Something went wrong writing the output file");
+        }
+        Commands::Disassemble(run_args) => {
+            let bytes = fs::read(&run_args.file).expect("Something went wrong reading the file");
+            match bytecode::decode(&bytes) {
+                Ok(program) => print!("{}", bytecode::disassemble(&program)),
+                Err(error) => {
+                    eprintln!("{}: {}", run_args.file, error);
+                    std::process::exit(1);
                 }
-            } else {
-                println!("Ejecutando programa...");
-                vm.run();
             }
         }
         Commands::Parse(run_args) => {
@@ -102,67 +430,33 @@ fn main() {
             let contents =
                 fs::read_to_string(file_name).expect("Something went wrong reading the file");
 
-            let mut parser = parse::Parser::new();
-            let instructions = parser.parse_file(&contents);
-            for instr in instructions {
-                println!("{:?}", instr);
+            let program = parse_program(parse::Parser::with_file(file_name), &contents);
+            match run_args.format {
+                Format::Debug => {
+                    for instr in program.instructions {
+                        println!("{:?}", instr);
+                    }
+                }
+                #[cfg(feature = "serde")]
+                Format::Json => {
+                    let json = serde_json::to_string_pretty(&program)
+                        .expect("Something went wrong serializing the program");
+                    println!("{}", json);
+                }
             }
         }
-        Commands::RunStdin => {
+        Commands::RunStdin(exec) => {
             let mut contents = String::new();
             std::io::stdin()
                 .read_to_string(&mut contents)
                 .expect("Something went wrong reading from stdin");
 
-            let mut parser = parse::Parser::new();
-            let instructions = parser.parse_file(&contents);
-            let mut vm = vm::VM::new(instructions);
-
-            if cli.debug {
-                println!("Ejecutando programa en modo depuración...");
-                loop {
-                    println!("Seleccione una opción:");
-                    println!("1. Ejecutar una instrucción");
-                    println!("2. Ejecutar todo el programa");
-                    println!("3. Ver contenido de la pila");
-                    println!("4. Ver contenido de las variables");
-                    println!("5. Salir");
-
-                    let mut choice = String::new();
-                    std::io::stdin()
-                        .read_line(&mut choice)
-                        .expect("Failed to read line");
-                    let choice = choice.trim();
-
-                    match choice {
-                        "1" => {
-                            println!("Instruccion actual: {:?}", vm.current_instruction());
-                            vm.step();
-                            println!("Instruccion siguiente: {:?}", vm.current_instruction());
-                        }
-                        "2" => {
-                            vm.run();
-                        }
-                        "3" => {
-                            println!("Contenido de la pila:");
-                            vm.print_stack();
-                        }
-                        "4" => {
-                            println!("Contenido de las variables:");
-                            vm.print_vars();
-                        }
-                        "5" => {
-                            break;
-                        }
-                        _ => {
-                            println!("Opción no válida, por favor intente de nuevo.");
-                        }
-                    }
-                }
-            } else {
-                println!("Ejecutando programa...");
-                vm.run();
-            }
+            let program = parse_program(parse::Parser::new(), &contents);
+            execute(
+                vm::VM::with_config(program, config(&cli, vm::VmConfig::default())),
+                &cli,
+                exec,
+            );
         }
     }
 }
@@ -1,174 +1,550 @@
+use super::program::{Program, SourceMap};
 use super::vm::Instruction;
 use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
 use std::str::FromStr;
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// La instrucción necesita un operando que no se escribió.
+    MissingOperand {
+        expected: &'static str,
+    },
+    InvalidNumber,
+    UnknownInstruction,
+    UnknownLabel,
+    /// La etiqueta ya se definió antes en la línea indicada.
+    DuplicateLabel {
+        first_line: usize,
+    },
+    /// Falta la comilla que cierra la cadena.
+    UnterminatedString,
+    InvalidEscape,
+    /// Sobra un operando después de la instrucción.
+    ExtraOperand,
+    /// Hay algo escrito después de la definición de una etiqueta.
+    TextAfterLabel,
+}
+
+/// Error de ensamblado con su posición en el archivo fuente.
+///
+/// `line` y `columns` empiezan en 1; el final de `columns` es exclusivo.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub file: String,
+    pub line: usize,
+    pub columns: Range<usize>,
+    pub token: String,
+    pub line_text: String,
+}
+
+impl ParseError {
+    pub fn message(&self) -> String {
+        match &self.kind {
+            ParseErrorKind::MissingOperand { expected } => {
+                format!("la instrucción {} requiere {}", self.token, expected)
+            }
+            ParseErrorKind::InvalidNumber => {
+                format!("`{}` no es un número válido", self.token)
+            }
+            ParseErrorKind::UnknownInstruction => {
+                format!("instrucción desconocida: {}", self.token)
+            }
+            ParseErrorKind::UnknownLabel => format!("etiqueta no encontrada: {}", self.token),
+            ParseErrorKind::UnterminatedString => {
+                format!("cadena sin cerrar: {}", self.token)
+            }
+            ParseErrorKind::InvalidEscape => {
+                format!("secuencia de escape inválida en {}", self.token)
+            }
+            ParseErrorKind::ExtraOperand => format!("operando de más: {}", self.token),
+            ParseErrorKind::TextAfterLabel => format!(
+                "texto después de la etiqueta: {} (las instrucciones van en su propia línea)",
+                self.token
+            ),
+            ParseErrorKind::DuplicateLabel { first_line } => format!(
+                "etiqueta duplicada: {} (definida antes en la línea {})",
+                self.token, first_line
+            ),
+        }
+    }
+
+    /// Formatea el error al estilo de un compilador, con la línea original
+    /// y un indicador bajo el token problemático.
+    pub fn render(&self) -> String {
+        let line_no = self.line.to_string();
+        let gutter = " ".repeat(line_no.len());
+        let width = self.columns.end.saturating_sub(self.columns.start).max(1);
+        format!(
+            "error: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}\n",
+            self.message(),
+            gutter,
+            self.file,
+            self.line,
+            self.columns.start,
+            gutter,
+            line_no,
+            self.line_text,
+            gutter,
+            " ".repeat(self.columns.start - 1),
+            "^".repeat(width)
+        )
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file,
+            self.line,
+            self.columns.start,
+            self.message()
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Clase de nombre que aparece en un archivo `.vm`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// Nombre de instrucción, como `LOAD_VAR`.
+    Instruction,
+    /// `nombre:` al inicio de una línea.
+    LabelDefinition,
+    /// Operando de un salto o de `CALL`.
+    LabelReference,
+    /// Operando de `LOAD_VAR`, `STORE_VAR`, `LOAD_GLOBAL` o `STORE_GLOBAL`.
+    Variable,
+}
+
+/// Nombre encontrado en el archivo, con la misma convención de posiciones
+/// que `ParseError`. En una definición de etiqueta `columns` no incluye
+/// los dos puntos.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub kind: SymbolKind,
+    pub name: String,
+    pub line: usize,
+    pub columns: Range<usize>,
+}
+
+/// Clase del operando que lleva la instrucción, si es un nombre.
+pub fn operand_kind(mnemonic: &str) -> Option<SymbolKind> {
+    match mnemonic {
+        "JMP" | "JMPEQ" | "JMPNE" | "JMPGT" | "JMPLT" | "JMPGE" | "JMPLE" | "JMPT" | "JMPF"
+        | "CALL" => Some(SymbolKind::LabelReference),
+        "LOAD_VAR" | "STORE_VAR" | "LOAD_GLOBAL" | "STORE_GLOBAL" => Some(SymbolKind::Variable),
+        _ => None,
+    }
+}
+
+/// Token de una línea junto con sus columnas (base 1, fin exclusivo).
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    start: usize,
+    end: usize,
+}
+
 pub struct Parser {
+    file: String,
     instructions: Vec<Instruction>,
+    source_map: SourceMap,
     labels: HashMap<String, usize>,
+    label_lines: HashMap<String, usize>,
+    errors: Vec<ParseError>,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Parser {
     pub fn new() -> Self {
+        Self::with_file("<stdin>")
+    }
+
+    /// Crea un parser que reporta los errores con el nombre de archivo dado.
+    pub fn with_file(file: &str) -> Self {
         Parser {
+            file: file.to_string(),
             instructions: Vec::new(),
+            source_map: SourceMap::new(file),
             labels: HashMap::new(),
+            label_lines: HashMap::new(),
+            errors: Vec::new(),
         }
     }
 
-    fn strip_comments<'a>(line: &'a str) -> &'a str {
-        if let Some(pos) = line.find(';') {
-            &line[..pos]
-        } else {
-            line
+    /// Divide la línea en tokens separados por espacios, ignorando el
+    /// comentario que empieza con `;`. Las cadenas entre comillas forman un
+    /// solo token aunque contengan espacios o `;`.
+    fn tokenize(line: &str) -> Vec<Token<'_>> {
+        let mut tokens = Vec::new();
+        let mut current: Option<(usize, usize)> = None;
+        let mut in_string = false;
+        let mut escaped = false;
+        let mut column = 0;
+        let mut end = line.len();
+        for (offset, c) in line.char_indices() {
+            column += 1;
+            if in_string {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == '"' {
+                    in_string = false;
+                    if let Some((byte, start)) = current.take() {
+                        tokens.push(Token {
+                            text: &line[byte..offset + 1],
+                            start,
+                            end: column + 1,
+                        });
+                    }
+                }
+                continue;
+            }
+            if c == ';' || c.is_whitespace() {
+                if let Some((byte, start)) = current.take() {
+                    tokens.push(Token {
+                        text: &line[byte..offset],
+                        start,
+                        end: column,
+                    });
+                }
+                if c == ';' {
+                    end = offset;
+                    break;
+                }
+            } else {
+                if current.is_none() {
+                    current = Some((offset, column));
+                }
+                in_string = c == '"';
+            }
+        }
+        if let Some((byte, start)) = current {
+            tokens.push(Token {
+                text: &line[byte..end],
+                start,
+                end: start + line[byte..end].chars().count(),
+            });
         }
+        tokens
     }
 
-    pub fn parse_file(&mut self, contents: &str) -> Vec<Instruction> {
-        // Primera pasada: almacenar etiquetas y sus índices
-
-        let predicate = |line: &&str| !Parser::strip_comments(line.trim()).is_empty();
-        let contents = contents.lines().filter(predicate);
-
-        let mut labels = 0;
-        for (ix, line) in contents.clone().enumerate() {
-            if line.ends_with(':') {
-                if self.labels.contains_key(line.trim_end_matches(':')) {
-                    panic!("Etiqueta duplicada: {}", line);
-                } else {
-                    let n_ins = if (ix as i32 - labels as i32) < 0 {
-                        0
-                    } else {
-                        ix - labels
-                    };
-                    self.labels
-                        .insert(line.trim_end_matches(':').to_string(), n_ins as usize);
-                    labels = labels + 1;
-                }
+    /// Recorre el archivo sin ensamblarlo y devuelve los nombres que
+    /// aparecen en él. No se detiene en los errores, así que sirve para
+    /// analizar archivos a medio escribir.
+    pub fn symbols(contents: &str) -> Vec<Symbol> {
+        let mut symbols = Vec::new();
+        for (ix, line) in contents.lines().enumerate() {
+            let symbol = |kind, name: &str, start: usize| Symbol {
+                kind,
+                name: name.to_string(),
+                line: ix + 1,
+                columns: start..start + name.chars().count(),
+            };
+            let tokens = Parser::tokenize(line);
+            let Some(first) = tokens.first() else {
+                continue;
+            };
+            if first.text.ends_with(':') {
+                let name = first.text.trim_end_matches(':');
+                symbols.push(symbol(SymbolKind::LabelDefinition, name, first.start));
+                continue;
             }
-        }
-        for (_, line) in contents.enumerate() {
-            let instr = self.parse_line(line); // index y no len
-            if let Some(instruction) = instr {
-                self.instructions.push(instruction);
+            symbols.push(symbol(SymbolKind::Instruction, first.text, first.start));
+            if let (Some(kind), Some(operand)) = (operand_kind(first.text), tokens.get(1)) {
+                symbols.push(symbol(kind, operand.text, operand.start));
             }
         }
+        symbols
+    }
 
-        // Segunda pasada: resolver etiquetas para instrucciones de salto
-        // self.resolve_labels();
-        self.instructions.clone()
+    /// Decodifica una cadena entre comillas con sus secuencias de escape.
+    fn unescape(&self, line_no: usize, line: &str, token: Token) -> Result<String, ParseError> {
+        let mut value = String::new();
+        let mut chars = token.text.chars().skip(1);
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return Ok(value),
+                '\\' => match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('r') => value.push('\r'),
+                    Some('0') => value.push('\0'),
+                    Some('\\') => value.push('\\'),
+                    Some('"') => value.push('"'),
+                    Some(_) => {
+                        return Err(self.error(ParseErrorKind::InvalidEscape, line_no, line, token))
+                    }
+                    None => break,
+                },
+                c => value.push(c),
+            }
+        }
+        Err(self.error(ParseErrorKind::UnterminatedString, line_no, line, token))
     }
 
-    fn parse_line(&mut self, line: &str) -> Option<Instruction> {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.is_empty() {
-            return None;
+    fn error(&self, kind: ParseErrorKind, line: usize, text: &str, token: Token) -> ParseError {
+        ParseError {
+            kind,
+            file: self.file.clone(),
+            line,
+            columns: token.start..token.end,
+            token: token.text.to_string(),
+            line_text: text.to_string(),
         }
+    }
 
-        match parts[0] {
-            "LOAD_CONST" => {
-                if parts.len() < 2 {
-                    panic!("La instrucción LOAD_CONST requiere un operando numérico");
-                }
-                if let Ok(val) = f64::from_str(parts[1]) {
-                    if val.fract() == 0.0 {
-                        Some(Instruction::LoadConstInt(val as i64))
+    /// Ensambla el programa completo. Si hay errores se reportan todos,
+    /// no solo el primero.
+    pub fn parse_file(&mut self, contents: &str) -> Result<Program, Vec<ParseError>> {
+        let lines: Vec<(usize, &str)> = contents
+            .lines()
+            .enumerate()
+            .map(|(ix, line)| (ix + 1, line))
+            .filter(|(_, line)| !Parser::tokenize(line).is_empty())
+            .collect();
+
+        // Primera pasada: almacenar etiquetas y sus índices
+        let mut n_ins = 0;
+        for &(line_no, line) in &lines {
+            let tokens = Parser::tokenize(line);
+            match tokens.first() {
+                Some(token) if token.text.ends_with(':') => {
+                    let name = token.text.trim_end_matches(':');
+                    if let Some(&first_line) = self.label_lines.get(name) {
+                        let error = self.error(
+                            ParseErrorKind::DuplicateLabel { first_line },
+                            line_no,
+                            line,
+                            Token {
+                                text: name,
+                                start: token.start,
+                                end: token.start + name.chars().count(),
+                            },
+                        );
+                        self.errors.push(error);
                     } else {
-                        Some(Instruction::LoadConstFloat(val))
+                        self.labels.insert(name.to_string(), n_ins);
+                        self.label_lines.insert(name.to_string(), line_no);
                     }
-                } else {
-                    panic!("LOAD_CONST requiere un número válido");
                 }
+                _ => n_ins += 1,
             }
-            "LOAD_VAR" => {
-                if parts.len() < 2 {
-                    panic!("La instrucción LOAD_VAR requiere un nombre de variable");
+        }
+
+        // Segunda pasada: traducir instrucciones resolviendo las etiquetas
+        for &(line_no, line) in &lines {
+            match self.parse_line(line_no, line) {
+                Ok(Some(instruction)) => {
+                    self.instructions.push(instruction);
+                    self.source_map.push(line_no, line);
                 }
-                Some(Instruction::LoadVar(parts[1].to_string()))
+                Ok(None) => {}
+                Err(error) => self.errors.push(error),
             }
-            "STORE_VAR" => {
-                if parts.len() < 2 {
-                    panic!("La instrucción STORE_VAR requiere un nombre de variable");
-                }
-                Some(Instruction::StoreVar(parts[1].to_string()))
-            }
-            "ADD" => Some(Instruction::Add),
-            "SUB" => Some(Instruction::Sub),
-            "MUL" => Some(Instruction::Mul),
-            "DIV" => Some(Instruction::Div),
-            "PRINT" => Some(Instruction::Print),
-            "READ" => Some(Instruction::Read),
-            "POW" => Some(Instruction::Pow),
-            "MOD" => Some(Instruction::Mod),
-            "JMP" => {
-                if parts.len() < 2 {
-                    panic!("La instrucción JMP requiere una etiqueta");
-                }
-                match self.labels.get(parts[1]) {
-                    Some(ix) => Some(Instruction::Jmp(ix.to_owned())),
-                    None => panic!("Etiqueta no encontrada: {}", parts[1]),
-                }
+        }
+
+        self.errors
+            .sort_by_key(|error| (error.line, error.columns.start));
+        if self.errors.is_empty() {
+            Ok(Program {
+                instructions: self.instructions.clone(),
+                labels: self.labels.clone(),
+                source_map: self.source_map.clone(),
+            })
+        } else {
+            Err(self.errors.clone())
+        }
+    }
+
+    fn operand<'a>(
+        &self,
+        line_no: usize,
+        line: &str,
+        parts: &[Token<'a>],
+        expected: &'static str,
+    ) -> Result<Token<'a>, ParseError> {
+        parts.get(1).copied().ok_or_else(|| {
+            self.error(
+                ParseErrorKind::MissingOperand { expected },
+                line_no,
+                line,
+                parts[0],
+            )
+        })
+    }
+
+    fn label(&self, line_no: usize, line: &str, parts: &[Token]) -> Result<usize, ParseError> {
+        let token = self.operand(line_no, line, parts, "una etiqueta")?;
+        match self.labels.get(token.text) {
+            Some(ix) => Ok(*ix),
+            None => Err(self.error(ParseErrorKind::UnknownLabel, line_no, line, token)),
+        }
+    }
+
+    /// Operando entero no negativo, como el de `PICK` o `NEW_ARRAY`.
+    fn count(
+        &self,
+        line_no: usize,
+        line: &str,
+        parts: &[Token],
+        expected: &'static str,
+    ) -> Result<usize, ParseError> {
+        let token = self.operand(line_no, line, parts, expected)?;
+        usize::from_str(token.text)
+            .map_err(|_| self.error(ParseErrorKind::InvalidNumber, line_no, line, token))
+    }
+
+    fn constant(
+        &self,
+        line_no: usize,
+        line: &str,
+        token: Token,
+    ) -> Result<Instruction, ParseError> {
+        let text = token.text;
+        if text.starts_with('"') {
+            return Ok(Instruction::LoadConstStr(
+                self.unescape(line_no, line, token)?,
+            ));
+        }
+        if let Ok(val) = bool::from_str(text) {
+            return Ok(Instruction::LoadConstBool(val));
+        }
+        // Los enteros se leen directo para no perder precisión
+        if let Ok(val) = i64::from_str(text) {
+            return Ok(Instruction::LoadConstInt(val));
+        }
+        match f64::from_str(text) {
+            Ok(val) if val.fract() == 0.0 && val.abs() < i64::MAX as f64 => {
+                Ok(Instruction::LoadConstInt(val as i64))
             }
-            "JMPEQ" => {
-                if parts.len() < 2 {
-                    panic!("La instrucción JMPEQ requiere una etiqueta");
-                }
-                match self.labels.get(parts[1]) {
-                    Some(ix) => Some(Instruction::JmpEq(ix.to_owned())),
-                    None => panic!("Etiqueta no encontrada: {}", parts[1]),
+            Ok(val) => Ok(Instruction::LoadConstFloat(val)),
+            Err(_) => Err(self.error(ParseErrorKind::InvalidNumber, line_no, line, token)),
+        }
+    }
+
+    fn parse_line(&self, line_no: usize, line: &str) -> Result<Option<Instruction>, ParseError> {
+        let parts = Parser::tokenize(line);
+        if parts.is_empty() {
+            return Ok(None);
+        }
+        if parts[0].text.ends_with(':') {
+            return match parts.get(1) {
+                Some(&token) => {
+                    Err(self.error(ParseErrorKind::TextAfterLabel, line_no, line, token))
                 }
+                None => Ok(None),
+            };
+        }
+
+        let instruction = match parts[0].text {
+            "LOAD_CONST" => {
+                let token = self.operand(line_no, line, &parts, "un valor constante")?;
+                self.constant(line_no, line, token)?
             }
-            "JMPNE" => {
-                if parts.len() < 2 {
-                    panic!("La instrucción JMPNE requiere una etiqueta");
-                }
-                match self.labels.get(parts[1]) {
-                    Some(ix) => Some(Instruction::JmpNe(ix.to_owned())),
-                    None => panic!("Etiqueta no encontrada: {}", parts[1]),
-                }
+            "LOAD_VAR" => {
+                let token = self.operand(line_no, line, &parts, "un nombre de variable")?;
+                Instruction::LoadVar(token.text.to_string())
             }
-            "JMPGT" => {
-                if parts.len() < 2 {
-                    panic!("La instrucción JMPGT requiere una etiqueta");
-                }
-                match self.labels.get(parts[1]) {
-                    Some(ix) => Some(Instruction::JmpGt(ix.to_owned())),
-                    None => panic!("Etiqueta no encontrada: {}", parts[1]),
-                }
+            "STORE_VAR" => {
+                let token = self.operand(line_no, line, &parts, "un nombre de variable")?;
+                Instruction::StoreVar(token.text.to_string())
             }
-            "JMPLT" => {
-                if parts.len() < 2 {
-                    panic!("La instrucción JMPLT requiere una etiqueta");
-                }
-                match self.labels.get(parts[1]) {
-                    Some(ix) => Some(Instruction::JmpLt(ix.to_owned())),
-                    None => panic!("Etiqueta no encontrada: {}", parts[1]),
-                }
+            "LOAD_GLOBAL" => {
+                let token = self.operand(line_no, line, &parts, "un nombre de variable")?;
+                Instruction::LoadGlobal(token.text.to_string())
             }
-            "JMPGE" => {
-                if parts.len() < 2 {
-                    panic!("La instrucción JMPGE requiere una etiqueta");
-                }
-                match self.labels.get(parts[1]) {
-                    Some(ix) => Some(Instruction::JmpGe(ix.to_owned())),
-                    None => panic!("Etiqueta no encontrada: {}", parts[1]),
-                }
+            "STORE_GLOBAL" => {
+                let token = self.operand(line_no, line, &parts, "un nombre de variable")?;
+                Instruction::StoreGlobal(token.text.to_string())
             }
-            "JMPLE" => {
-                if parts.len() < 2 {
-                    panic!("La instrucción JMPLE requiere una etiqueta");
-                }
-                match self.labels.get(parts[1]) {
-                    Some(ix) => Some(Instruction::JmpLe(ix.to_owned())),
-                    None => panic!("Etiqueta no encontrada: {}", parts[1]),
-                }
+            "ADD" => Instruction::Add,
+            "SUB" => Instruction::Sub,
+            "MUL" => Instruction::Mul,
+            "DIV" => Instruction::Div,
+            "IDIV" => Instruction::IDiv,
+            "PRINT" => Instruction::Print,
+            "READ" => Instruction::Read,
+            "POW" => Instruction::Pow,
+            "MOD" => Instruction::Mod,
+            "JMP" => Instruction::Jmp(self.label(line_no, line, &parts)?),
+            "JMPEQ" => Instruction::JmpEq(self.label(line_no, line, &parts)?),
+            "JMPNE" => Instruction::JmpNe(self.label(line_no, line, &parts)?),
+            "JMPGT" => Instruction::JmpGt(self.label(line_no, line, &parts)?),
+            "JMPLT" => Instruction::JmpLt(self.label(line_no, line, &parts)?),
+            "JMPGE" => Instruction::JmpGe(self.label(line_no, line, &parts)?),
+            "JMPLE" => Instruction::JmpLe(self.label(line_no, line, &parts)?),
+            "DUP" => Instruction::Dup,
+            "POP" => Instruction::Pop,
+            "SWAP" => Instruction::Swap,
+            "OVER" => Instruction::Over,
+            "ROT" => Instruction::Rot,
+            "PICK" => {
+                Instruction::Pick(self.count(line_no, line, &parts, "una posición de la pila")?)
             }
+            "EQ" => Instruction::Eq,
+            "NE" => Instruction::Ne,
+            "LT" => Instruction::Lt,
+            "LE" => Instruction::Le,
+            "GT" => Instruction::Gt,
+            "GE" => Instruction::Ge,
+            "JMPT" => Instruction::JmpTrue(self.label(line_no, line, &parts)?),
+            "JMPF" => Instruction::JmpFalse(self.label(line_no, line, &parts)?),
+            "AND" => Instruction::And,
+            "OR" => Instruction::Or,
+            "NOT" => Instruction::Not,
+            "XOR" => Instruction::Xor,
+            "CONCAT" => Instruction::Concat,
+            "LEN" => Instruction::Len,
+            "SUBSTR" => Instruction::Substr,
+            "TO_STR" => Instruction::ToStr,
+            "TO_NUM" => Instruction::ToNum,
+            "TO_FLOAT" => Instruction::ToFloat,
+            "NUM" => Instruction::Num,
+            "DEN" => Instruction::Den,
+            "NEW_ARRAY" => Instruction::NewArray(self.count(
+                line_no,
+                line,
+                &parts,
+                "un número de elementos",
+            )?),
+            "ARRAY_GET" => Instruction::ArrayGet,
+            "ARRAY_SET" => Instruction::ArraySet,
+            "ARRAY_LEN" => Instruction::ArrayLen,
+            "ARRAY_PUSH" => Instruction::ArrayPush,
+            "ARRAY_POP" => Instruction::ArrayPop,
+            "NEW_MAP" => Instruction::NewMap,
+            "MAP_GET" => Instruction::MapGet,
+            "MAP_SET" => Instruction::MapSet,
+            "MAP_HAS" => Instruction::MapHas,
+            "MAP_DEL" => Instruction::MapDel,
+            "MAP_KEYS" => Instruction::MapKeys,
+            "CALL" => Instruction::Call(self.label(line_no, line, &parts)?),
+            "RET" => Instruction::Ret,
             _ => {
-                if line.ends_with(':') {
-                    None
-                } else {
-                    panic!("Instrucción desconocida: {}", parts[0]);
-                }
+                return Err(self.error(ParseErrorKind::UnknownInstruction, line_no, line, parts[0]))
             }
+        };
+        let operands = match parts[0].text {
+            "LOAD_CONST" | "PICK" | "NEW_ARRAY" => 1,
+            mnemonic if operand_kind(mnemonic).is_some() => 1,
+            _ => 0,
+        };
+        if let Some(&token) = parts.get(operands + 1) {
+            return Err(self.error(ParseErrorKind::ExtraOperand, line_no, line, token));
         }
+        Ok(Some(instruction))
     }
 }
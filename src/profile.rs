@@ -0,0 +1,213 @@
+use super::program::Program;
+use super::vm::VM;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Bloque delimitado por etiquetas: va de una etiqueta (o del principio del
+/// programa) hasta la siguiente.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub name: String,
+    pub start: usize,
+    pub end: usize,
+    /// Veces que se ejecutó su primera instrucción.
+    pub entries: u64,
+    /// Instrucciones ejecutadas dentro del bloque.
+    pub steps: u64,
+}
+
+/// Cuenta lo que ejecuta la VM. Hay que llamar a `record` antes de cada
+/// paso y a `finish` al terminar.
+pub struct Profiler {
+    counts: Vec<u64>,
+    steps: u64,
+    max_stack: usize,
+    max_calls: usize,
+    /// Pasos por pila de llamadas, como direcciones de las subrutinas.
+    stacks: HashMap<Vec<usize>, u64>,
+    /// Pila de llamadas del paso actual, para no pedir memoria en cada uno.
+    current: Vec<usize>,
+    start: Instant,
+    elapsed: Option<Duration>,
+}
+
+impl Profiler {
+    pub fn new(program: &Program) -> Self {
+        Profiler {
+            counts: vec![0; program.len()],
+            steps: 0,
+            max_stack: 0,
+            max_calls: 0,
+            stacks: HashMap::new(),
+            current: Vec::new(),
+            start: Instant::now(),
+            elapsed: None,
+        }
+    }
+
+    /// Registra la instrucción que la VM está por ejecutar.
+    pub fn record(&mut self, vm: &VM) {
+        self.observe(vm);
+        let Some(count) = self.counts.get_mut(vm.ip()) else {
+            return;
+        };
+        *count += 1;
+        self.steps += 1;
+        self.current.clear();
+        self.current
+            .extend(vm.call_stack().iter().map(|frame| frame.function));
+        match self.stacks.get_mut(self.current.as_slice()) {
+            Some(steps) => *steps += 1,
+            None => {
+                self.stacks.insert(self.current.clone(), 1);
+            }
+        }
+    }
+
+    /// Detiene el reloj y toma en cuenta el estado final de la VM.
+    pub fn finish(&mut self, vm: &VM) {
+        self.observe(vm);
+        self.elapsed = Some(self.start.elapsed());
+    }
+
+    fn observe(&mut self, vm: &VM) {
+        self.max_stack = self.max_stack.max(vm.stack().len());
+        self.max_calls = self.max_calls.max(vm.call_stack().len());
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Veces que se ejecutó cada instrucción, por índice.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Tiempo de pared desde `new` hasta `finish`, incluida la espera de
+    /// `READ`.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed.unwrap_or_else(|| self.start.elapsed())
+    }
+
+    pub fn blocks(&self, program: &Program) -> Vec<Block> {
+        let mut starts: Vec<usize> = program
+            .labels
+            .values()
+            .copied()
+            .filter(|&ix| ix < program.len())
+            .chain(std::iter::once(0))
+            .collect();
+        starts.sort_unstable();
+        starts.dedup();
+        starts
+            .iter()
+            .enumerate()
+            .filter(|&(_, &start)| start < program.len())
+            .map(|(ix, &start)| {
+                let end = starts.get(ix + 1).copied().unwrap_or(program.len());
+                Block {
+                    name: program.label_at(start).unwrap_or("<inicio>").to_string(),
+                    start,
+                    end,
+                    entries: self.counts[start],
+                    steps: self.counts[start..end].iter().sum(),
+                }
+            })
+            .collect()
+    }
+
+    /// Reporte con los totales, las `top` instrucciones más ejecutadas y
+    /// los bloques ordenados por pasos.
+    pub fn report<W: Write>(&self, program: &Program, top: usize, out: &mut W) -> io::Result<()> {
+        let elapsed = self.elapsed();
+        writeln!(out, "Perfil de ejecución:")?;
+        writeln!(out, "  pasos: {}", self.steps)?;
+        write!(out, "  tiempo: {:.3?}", elapsed)?;
+        if !elapsed.is_zero() {
+            let rate = self.steps as f64 / elapsed.as_secs_f64();
+            write!(out, " ({:.0} pasos/s)", rate)?;
+        }
+        writeln!(out)?;
+        writeln!(out, "  pila máxima: {} valor(es)", self.max_stack)?;
+        writeln!(out, "  llamadas anidadas: {}", self.max_calls)?;
+
+        let mut hot: Vec<usize> = (0..self.counts.len())
+            .filter(|&ix| self.counts[ix] > 0)
+            .collect();
+        hot.sort_by_key(|&ix| (std::cmp::Reverse(self.counts[ix]), ix));
+        writeln!(out)?;
+        writeln!(
+            out,
+            "{:<7} | {:<6} | {:<13} | {:>10} | {:>6}",
+            "Instr", "Línea", "Instrucción", "Veces", "%"
+        )?;
+        writeln!(out, "{}", "-".repeat(54))?;
+        for ix in hot.into_iter().take(top) {
+            let line = program
+                .line_of(ix)
+                .map_or_else(|| "-".to_string(), |line| line.to_string());
+            writeln!(
+                out,
+                "{:<7} | {:<6} | {:<13} | {:>10} | {:>5.1}%",
+                format!("#{}", ix),
+                line,
+                program.instructions[ix].mnemonic(),
+                self.counts[ix],
+                self.percent(self.counts[ix])
+            )?;
+        }
+
+        let mut blocks = self.blocks(program);
+        blocks.sort_by_key(|block| (std::cmp::Reverse(block.steps), block.start));
+        writeln!(out)?;
+        writeln!(
+            out,
+            "{:<15} | {:<9} | {:>10} | {:>10} | {:>6}",
+            "Bloque", "Rango", "Entradas", "Pasos", "%"
+        )?;
+        writeln!(out, "{}", "-".repeat(62))?;
+        for block in blocks.iter().filter(|block| block.steps > 0) {
+            writeln!(
+                out,
+                "{:<15} | {:<9} | {:>10} | {:>10} | {:>5.1}%",
+                block.name,
+                format!("#{}-#{}", block.start, block.end - 1),
+                block.entries,
+                block.steps,
+                self.percent(block.steps)
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Pasos por pila de llamadas en el formato "colapsado" de las
+    /// herramientas de flamegraph: `<principal>;f;g 42`, una por línea.
+    pub fn write_collapsed<W: Write>(&self, program: &Program, out: &mut W) -> io::Result<()> {
+        let mut lines: Vec<(String, u64)> = self
+            .stacks
+            .iter()
+            .map(|(functions, &steps)| {
+                let names = functions
+                    .iter()
+                    .map(|&function| program.label_at(function).unwrap_or("<anónima>"));
+                let path: Vec<&str> = std::iter::once("<principal>").chain(names).collect();
+                (path.join(";"), steps)
+            })
+            .collect();
+        lines.sort();
+        for (path, steps) in lines {
+            writeln!(out, "{} {}", path, steps)?;
+        }
+        Ok(())
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        if self.steps == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / self.steps as f64
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use super::vm::Instruction;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Línea del archivo fuente de la que salió una instrucción.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SourceEntry {
+    pub line: usize,
+    pub text: String,
+}
+
+/// Relaciona cada índice de instrucción con su posición en el archivo `.vm`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SourceMap {
+    pub file: String,
+    pub entries: Vec<SourceEntry>,
+}
+
+/// Posición de una instrucción, prestada del mapa de fuentes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLocation<'a> {
+    pub file: &'a str,
+    pub line: usize,
+    pub text: &'a str,
+}
+
+impl fmt::Display for SourceLocation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.text)
+    }
+}
+
+impl SourceMap {
+    pub fn new(file: &str) -> Self {
+        SourceMap {
+            file: file.to_string(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, line: usize, text: &str) {
+        self.entries.push(SourceEntry {
+            line,
+            text: text.trim().to_string(),
+        });
+    }
+
+    pub fn get(&self, ix: usize) -> Option<SourceLocation<'_>> {
+        self.entries.get(ix).map(|entry| SourceLocation {
+            file: &self.file,
+            line: entry.line,
+            text: &entry.text,
+        })
+    }
+
+    /// Primera instrucción generada por la línea `line` del archivo, si hay.
+    pub fn instruction_at_line(&self, line: usize) -> Option<usize> {
+        self.entries.iter().position(|entry| entry.line == line)
+    }
+}
+
+/// Resultado de ensamblar un archivo `.vm`: las instrucciones ya resueltas,
+/// la tabla de etiquetas (nombre → índice de instrucción) y el mapa de
+/// fuentes de cada instrucción.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(serialize_with = "crate::serde_helpers::sorted")
+    )]
+    pub labels: HashMap<String, usize>,
+    pub source_map: SourceMap,
+}
+
+impl Program {
+    pub fn len(&self) -> usize {
+        self.instructions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instructions.is_empty()
+    }
+
+    pub fn get(&self, ix: usize) -> Option<&Instruction> {
+        self.instructions.get(ix)
+    }
+
+    /// Línea del archivo fuente (base 1) de la instrucción `ix`.
+    pub fn line_of(&self, ix: usize) -> Option<usize> {
+        self.source_map.get(ix).map(|location| location.line)
+    }
+
+    /// Etiqueta que apunta a la instrucción `ix`. Si hay varias se elige
+    /// la menor alfabéticamente para que el resultado sea estable.
+    pub fn label_at(&self, ix: usize) -> Option<&str> {
+        self.labels
+            .iter()
+            .filter(|(_, &target)| target == ix)
+            .map(|(name, _)| name.as_str())
+            .min()
+    }
+
+    pub fn location(&self, ix: usize) -> Option<SourceLocation<'_>> {
+        self.source_map.get(ix)
+    }
+}
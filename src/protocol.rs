@@ -0,0 +1,63 @@
+use serde_json::Value;
+use std::io::{self, BufRead, Read, Write};
+
+/// Tamaño máximo del cuerpo de un mensaje. El `Content-Length` lo manda el
+/// cliente y no se puede reservar memoria a ciegas con él.
+pub const MAX_MESSAGE_LEN: usize = 64 << 20;
+
+/// Lee un mensaje JSON precedido por el encabezado `Content-Length`.
+/// Devuelve `None` cuando la entrada se acaba entre dos mensajes.
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return match length {
+                None => Ok(None),
+                Some(_) => Err(invalid("la entrada terminó dentro de un encabezado")),
+            };
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        // Otros encabezados, como `Content-Type`, se ignoran.
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                let value = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid("Content-Length inválido"))?;
+                length = Some(value);
+            }
+        }
+    }
+    let length: usize = length.ok_or_else(|| invalid("falta el encabezado Content-Length"))?;
+    if length > MAX_MESSAGE_LEN {
+        return Err(invalid(&format!(
+            "Content-Length de {} bytes supera el máximo de {}",
+            length, MAX_MESSAGE_LEN
+        )));
+    }
+    let mut body = Vec::new();
+    reader.take(length as u64).read_to_end(&mut body)?;
+    if body.len() < length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "la entrada terminó dentro de un mensaje",
+        ));
+    }
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|err| invalid(&err.to_string()))
+}
+
+pub fn write_message<W: Write>(writer: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,165 @@
+use super::vm::{Instruction, MapKey, Value};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+
+/// Serializa un `HashMap` con las llaves ordenadas para que el JSON no
+/// cambie de una ejecución a otra.
+pub fn sorted<S, K, V>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    K: Serialize + Ord,
+    V: Serialize,
+{
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
+
+/// Enteros grandes como cadena decimal.
+pub mod bigint {
+    use super::*;
+    use num_bigint::BigInt;
+
+    pub fn serialize<S: Serializer>(big: &BigInt, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(big)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BigInt, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(D::Error::custom)
+    }
+}
+
+/// Fracciones como `[numerador, denominador]`.
+pub mod rational {
+    use super::*;
+    use num_rational::Rational64;
+
+    pub fn serialize<S: Serializer>(ratio: &Rational64, serializer: S) -> Result<S::Ok, S::Error> {
+        (ratio.numer(), ratio.denom()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Rational64, D::Error> {
+        let (numer, denom) = <(i64, i64)>::deserialize(deserializer)?;
+        if denom == 0 {
+            return Err(D::Error::custom("fracción con denominador cero"));
+        }
+        Ok(Rational64::new(numer, denom))
+    }
+}
+
+/// Mapas de la máquina como lista de pares `[llave, valor]`, porque en JSON
+/// las llaves solo pueden ser cadenas.
+pub mod entries {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        entries: &BTreeMap<MapKey, Value>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(entries.iter())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<BTreeMap<MapKey, Value>, D::Error> {
+        let pairs = Vec::<(MapKey, Value)>::deserialize(deserializer)?;
+        Ok(pairs.into_iter().collect())
+    }
+}
+
+/// Operando de una instrucción en JSON. En `LOAD_CONST` el tipo del valor
+/// decide la constante: entero, flotante, booleano o cadena.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Operand {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+}
+
+/// Forma de `Instruction` en JSON: `op` es siempre el mnemónico del
+/// ensamblador, de modo que renombrar una variante no cambia el formato.
+#[derive(Serialize, Deserialize)]
+pub struct InstructionRepr {
+    op: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    arg: Option<Operand>,
+}
+
+impl From<Instruction> for InstructionRepr {
+    fn from(instr: Instruction) -> Self {
+        let op = instr.mnemonic().to_string();
+        let arg = match instr {
+            Instruction::LoadConstFloat(val) => Some(Operand::Float(val)),
+            Instruction::LoadConstInt(val) => Some(Operand::Int(val)),
+            Instruction::LoadConstBool(val) => Some(Operand::Bool(val)),
+            Instruction::LoadConstStr(val)
+            | Instruction::LoadVar(val)
+            | Instruction::StoreVar(val)
+            | Instruction::LoadGlobal(val)
+            | Instruction::StoreGlobal(val) => Some(Operand::Str(val)),
+            Instruction::Pick(n) | Instruction::NewArray(n) => Some(Operand::Int(n as i64)),
+            instr => instr
+                .jump_target()
+                .map(|target| Operand::Int(target as i64)),
+        };
+        InstructionRepr { op, arg }
+    }
+}
+
+impl TryFrom<InstructionRepr> for Instruction {
+    type Error = String;
+
+    fn try_from(repr: InstructionRepr) -> Result<Self, Self::Error> {
+        let op = repr.op.as_str();
+        let name = |arg| match arg {
+            Some(Operand::Str(name)) => Ok(name),
+            _ => Err(format!("la instrucción {} requiere un nombre", op)),
+        };
+        let number = |arg| match arg {
+            Some(Operand::Int(n)) if n >= 0 => Ok(n as usize),
+            _ => Err(format!(
+                "la instrucción {} requiere un entero no negativo",
+                op
+            )),
+        };
+        let arg = repr.arg;
+        let instr = match op {
+            "LOAD_CONST" => match arg {
+                Some(Operand::Int(val)) => Instruction::LoadConstInt(val),
+                Some(Operand::Float(val)) => Instruction::LoadConstFloat(val),
+                Some(Operand::Bool(val)) => Instruction::LoadConstBool(val),
+                Some(Operand::Str(val)) => Instruction::LoadConstStr(val),
+                None => return Err("la instrucción LOAD_CONST requiere un valor".to_string()),
+            },
+            "LOAD_VAR" => Instruction::LoadVar(name(arg)?),
+            "STORE_VAR" => Instruction::StoreVar(name(arg)?),
+            "LOAD_GLOBAL" => Instruction::LoadGlobal(name(arg)?),
+            "STORE_GLOBAL" => Instruction::StoreGlobal(name(arg)?),
+            "JMP" => Instruction::Jmp(number(arg)?),
+            "JMPEQ" => Instruction::JmpEq(number(arg)?),
+            "JMPNE" => Instruction::JmpNe(number(arg)?),
+            "JMPGE" => Instruction::JmpGe(number(arg)?),
+            "JMPGT" => Instruction::JmpGt(number(arg)?),
+            "JMPLT" => Instruction::JmpLt(number(arg)?),
+            "JMPLE" => Instruction::JmpLe(number(arg)?),
+            "JMPT" => Instruction::JmpTrue(number(arg)?),
+            "JMPF" => Instruction::JmpFalse(number(arg)?),
+            "CALL" => Instruction::Call(number(arg)?),
+            "PICK" => Instruction::Pick(number(arg)?),
+            "NEW_ARRAY" => Instruction::NewArray(number(arg)?),
+            _ => {
+                let instr = Instruction::NO_OPERAND
+                    .iter()
+                    .find(|instr| instr.mnemonic() == op)
+                    .ok_or_else(|| format!("instrucción desconocida: {}", op))?;
+                if arg.is_some() {
+                    return Err(format!("la instrucción {} no lleva operando", op));
+                }
+                instr.clone()
+            }
+        };
+        Ok(instr)
+    }
+}
@@ -0,0 +1,209 @@
+use super::program::Program;
+use super::vm::{Instruction, StepOutcome, Value, VmError, VmErrorKind, VM};
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::str::FromStr;
+
+/// Formato de cada registro de la traza.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// Una línea legible por instrucción.
+    #[default]
+    Text,
+    /// Un objeto JSON por línea.
+    #[cfg(feature = "serde")]
+    Json,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(TraceFormat::Text),
+            #[cfg(feature = "serde")]
+            "json" => Ok(TraceFormat::Json),
+            _ => Err(format!("formato de traza desconocido: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for TraceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TraceFormat::Text => "text",
+            #[cfg(feature = "serde")]
+            TraceFormat::Json => "json",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Escritura de una variable hecha por la instrucción trazada.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VarWrite {
+    pub name: String,
+    pub global: bool,
+    pub value: String,
+}
+
+/// Lo que hizo una instrucción. Los valores ya vienen representados como
+/// texto porque las referencias al heap no significan nada fuera de la VM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub ip: usize,
+    pub line: Option<usize>,
+    pub mnemonic: &'static str,
+    pub stack_before: Vec<String>,
+    pub stack_after: Vec<String>,
+    pub write: Option<VarWrite>,
+    pub error: Option<String>,
+}
+
+impl Record {
+    fn text(&self) -> String {
+        let line = match self.line {
+            Some(line) => format!("línea {}", line),
+            None => "-".to_string(),
+        };
+        let mut out = format!(
+            "#{:<5} {:<10} {:<12} [{}] -> [{}]",
+            self.ip,
+            line,
+            self.mnemonic,
+            self.stack_before.join(", "),
+            self.stack_after.join(", ")
+        );
+        if let Some(write) = &self.write {
+            let scope = if write.global { "global" } else { "local" };
+            out += &format!("  {} {} = {}", scope, write.name, write.value);
+        }
+        if let Some(error) = &self.error {
+            out += &format!("  error: {}", error);
+        }
+        out
+    }
+
+    #[cfg(feature = "serde")]
+    fn json(&self) -> String {
+        let write = self.write.as_ref().map(|write| {
+            serde_json::json!({
+                "name": write.name,
+                "scope": if write.global { "global" } else { "local" },
+                "value": write.value,
+            })
+        });
+        serde_json::json!({
+            "ip": self.ip,
+            "line": self.line,
+            "mnemonic": self.mnemonic,
+            "stack_before": self.stack_before,
+            "stack_after": self.stack_after,
+            "write": write,
+            "error": self.error,
+        })
+        .to_string()
+    }
+}
+
+/// Instrucciones entre dos etiquetas: desde `from` (incluida) hasta `to`
+/// (excluida). Sin etiqueta se toma el principio o el final del programa.
+pub fn label_range(
+    program: &Program,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<Range<usize>, String> {
+    let resolve = |label: Option<&str>, default: usize| match label {
+        Some(name) => program
+            .labels
+            .get(name)
+            .copied()
+            .ok_or_else(|| format!("la etiqueta {} no existe", name)),
+        None => Ok(default),
+    };
+    Ok(resolve(from, 0)?..resolve(to, program.len())?)
+}
+
+/// Ejecuta la VM instrucción por instrucción y escribe un registro de cada
+/// una en `out`.
+pub struct Tracer<W: Write> {
+    out: W,
+    format: TraceFormat,
+    range: Option<Range<usize>>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(out: W, format: TraceFormat) -> Self {
+        Tracer {
+            out,
+            format,
+            range: None,
+        }
+    }
+
+    /// Solo se registran las instrucciones con índice dentro de `range`;
+    /// las demás se ejecutan sin dejar rastro.
+    pub fn set_range(&mut self, range: Range<usize>) {
+        self.range = Some(range);
+    }
+
+    /// Ejecuta una instrucción como `VM::step` y la registra. Un error al
+    /// escribir la traza se reporta como `VmErrorKind::Io`.
+    pub fn step(&mut self, vm: &mut VM) -> Result<StepOutcome, VmError> {
+        let ip = vm.ip();
+        let instr = match vm.current_instruction() {
+            Some(instr) if self.range.as_ref().is_none_or(|r| r.contains(&ip)) => instr.clone(),
+            _ => return vm.step(),
+        };
+        let stack_before = stack_repr(vm);
+        let result = vm.step();
+        let record = Record {
+            ip,
+            line: vm.program().line_of(ip),
+            mnemonic: instr.mnemonic(),
+            stack_before,
+            stack_after: stack_repr(vm),
+            write: match &result {
+                Ok(_) => var_write(vm, &instr),
+                Err(_) => None,
+            },
+            error: result.as_ref().err().map(|error| error.kind.to_string()),
+        };
+        let line = match self.format {
+            TraceFormat::Text => record.text(),
+            #[cfg(feature = "serde")]
+            TraceFormat::Json => record.json(),
+        };
+        writeln!(self.out, "{}", line).map_err(|err| VmError {
+            kind: VmErrorKind::Io(err.to_string()),
+            ip,
+            line: record.line,
+        })?;
+        result
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+fn stack_repr(vm: &VM) -> Vec<String> {
+    vm.stack().iter().map(|val| vm.heap().repr(val)).collect()
+}
+
+/// Variable que acaba de escribir `instr`, con su valor nuevo.
+fn var_write(vm: &VM, instr: &Instruction) -> Option<VarWrite> {
+    let name = vm.last_write()?;
+    let global = matches!(instr, Instruction::StoreGlobal(_)) || vm.call_stack().is_empty();
+    let value: &Value = if global {
+        vm.globals().get(name)?
+    } else {
+        vm.call_stack().last()?.locals.get(name)?
+    };
+    Some(VarWrite {
+        name: name.to_string(),
+        global,
+        value: vm.heap().repr(value),
+    })
+}
@@ -1,16 +1,48 @@
-use std::collections::HashMap;
-use std::io::{self};
+mod arith;
+mod heap;
+mod history;
 
-#[derive(Debug, Clone)]
+pub use arith::{ArithOp, OverflowMode};
+pub use heap::{GcReport, GcStats, Heap, HeapObject, HeapRef};
+
+use super::program::{Program, SourceLocation};
+use history::{FrameChange, History, UndoEntry, VarWrite};
+use num_bigint::BigInt;
+use num_rational::Rational64;
+use num_traits::ToPrimitive;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+/// En JSON cada instrucción es `{"op": "LOAD_VAR", "arg": "x"}`, con el
+/// mnemónico del ensamblador en `op`; `arg` se omite en las instrucciones
+/// sin operando. En `LOAD_CONST` el tipo de `arg` indica el de la constante.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(
+        into = "crate::serde_helpers::InstructionRepr",
+        try_from = "crate::serde_helpers::InstructionRepr"
+    )
+)]
 pub enum Instruction {
     LoadConstFloat(f64),
     LoadConstInt(i64),
+    LoadConstBool(bool),
+    LoadConstStr(String),
     LoadVar(String),
     StoreVar(String),
+    LoadGlobal(String),
+    StoreGlobal(String),
     Add,
     Sub,
     Mul,
     Div,
+    IDiv,
     Pow,
     Mod,
     Print,
@@ -22,235 +54,1481 @@ pub enum Instruction {
     JmpGt(usize),
     JmpLt(usize),
     JmpLe(usize),
+    Call(usize),
+    Ret,
+    Dup,
+    Pop,
+    Swap,
+    Over,
+    Rot,
+    Pick(usize),
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    JmpTrue(usize),
+    JmpFalse(usize),
+    And,
+    Or,
+    Not,
+    Xor,
+    Concat,
+    Len,
+    Substr,
+    ToStr,
+    ToNum,
+    ToFloat,
+    Num,
+    Den,
+    NewArray(usize),
+    ArrayGet,
+    ArraySet,
+    ArrayLen,
+    ArrayPush,
+    ArrayPop,
+    NewMap,
+    MapGet,
+    MapSet,
+    MapHas,
+    MapDel,
+    MapKeys,
+}
+
+impl Instruction {
+    /// Todas las instrucciones que no llevan operando.
+    pub const NO_OPERAND: &'static [Instruction] = &[
+        Instruction::Add,
+        Instruction::Sub,
+        Instruction::Mul,
+        Instruction::Div,
+        Instruction::IDiv,
+        Instruction::Pow,
+        Instruction::Mod,
+        Instruction::Print,
+        Instruction::Read,
+        Instruction::Ret,
+        Instruction::Dup,
+        Instruction::Pop,
+        Instruction::Swap,
+        Instruction::Over,
+        Instruction::Rot,
+        Instruction::Eq,
+        Instruction::Ne,
+        Instruction::Lt,
+        Instruction::Le,
+        Instruction::Gt,
+        Instruction::Ge,
+        Instruction::And,
+        Instruction::Or,
+        Instruction::Not,
+        Instruction::Xor,
+        Instruction::Concat,
+        Instruction::Len,
+        Instruction::Substr,
+        Instruction::ToStr,
+        Instruction::ToNum,
+        Instruction::ToFloat,
+        Instruction::Num,
+        Instruction::Den,
+        Instruction::ArrayGet,
+        Instruction::ArraySet,
+        Instruction::ArrayLen,
+        Instruction::ArrayPush,
+        Instruction::ArrayPop,
+        Instruction::NewMap,
+        Instruction::MapGet,
+        Instruction::MapSet,
+        Instruction::MapHas,
+        Instruction::MapDel,
+        Instruction::MapKeys,
+    ];
+
+    /// Nombre de la instrucción en el ensamblador.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::LoadConstFloat(_)
+            | Instruction::LoadConstInt(_)
+            | Instruction::LoadConstBool(_)
+            | Instruction::LoadConstStr(_) => "LOAD_CONST",
+            Instruction::LoadVar(_) => "LOAD_VAR",
+            Instruction::StoreVar(_) => "STORE_VAR",
+            Instruction::LoadGlobal(_) => "LOAD_GLOBAL",
+            Instruction::StoreGlobal(_) => "STORE_GLOBAL",
+            Instruction::Add => "ADD",
+            Instruction::Sub => "SUB",
+            Instruction::Mul => "MUL",
+            Instruction::Div => "DIV",
+            Instruction::IDiv => "IDIV",
+            Instruction::Pow => "POW",
+            Instruction::Mod => "MOD",
+            Instruction::Print => "PRINT",
+            Instruction::Read => "READ",
+            Instruction::Jmp(_) => "JMP",
+            Instruction::JmpEq(_) => "JMPEQ",
+            Instruction::JmpNe(_) => "JMPNE",
+            Instruction::JmpGe(_) => "JMPGE",
+            Instruction::JmpGt(_) => "JMPGT",
+            Instruction::JmpLt(_) => "JMPLT",
+            Instruction::JmpLe(_) => "JMPLE",
+            Instruction::Call(_) => "CALL",
+            Instruction::Ret => "RET",
+            Instruction::Dup => "DUP",
+            Instruction::Pop => "POP",
+            Instruction::Swap => "SWAP",
+            Instruction::Over => "OVER",
+            Instruction::Rot => "ROT",
+            Instruction::Pick(_) => "PICK",
+            Instruction::Eq => "EQ",
+            Instruction::Ne => "NE",
+            Instruction::Lt => "LT",
+            Instruction::Le => "LE",
+            Instruction::Gt => "GT",
+            Instruction::Ge => "GE",
+            Instruction::JmpTrue(_) => "JMPT",
+            Instruction::JmpFalse(_) => "JMPF",
+            Instruction::And => "AND",
+            Instruction::Or => "OR",
+            Instruction::Not => "NOT",
+            Instruction::Xor => "XOR",
+            Instruction::Concat => "CONCAT",
+            Instruction::Len => "LEN",
+            Instruction::Substr => "SUBSTR",
+            Instruction::ToStr => "TO_STR",
+            Instruction::ToNum => "TO_NUM",
+            Instruction::ToFloat => "TO_FLOAT",
+            Instruction::Num => "NUM",
+            Instruction::Den => "DEN",
+            Instruction::NewArray(_) => "NEW_ARRAY",
+            Instruction::ArrayGet => "ARRAY_GET",
+            Instruction::ArraySet => "ARRAY_SET",
+            Instruction::ArrayLen => "ARRAY_LEN",
+            Instruction::ArrayPush => "ARRAY_PUSH",
+            Instruction::ArrayPop => "ARRAY_POP",
+            Instruction::NewMap => "NEW_MAP",
+            Instruction::MapGet => "MAP_GET",
+            Instruction::MapSet => "MAP_SET",
+            Instruction::MapHas => "MAP_HAS",
+            Instruction::MapDel => "MAP_DEL",
+            Instruction::MapKeys => "MAP_KEYS",
+        }
+    }
+
+    /// Dirección a la que puede saltar la instrucción (incluido `CALL`).
+    pub fn jump_target(&self) -> Option<usize> {
+        match self {
+            Instruction::Jmp(target)
+            | Instruction::JmpEq(target)
+            | Instruction::JmpNe(target)
+            | Instruction::JmpGe(target)
+            | Instruction::JmpGt(target)
+            | Instruction::JmpLt(target)
+            | Instruction::JmpLe(target)
+            | Instruction::JmpTrue(target)
+            | Instruction::JmpFalse(target)
+            | Instruction::Call(target) => Some(*target),
+            _ => None,
+        }
+    }
 }
 
+/// En JSON cada valor es `{"type": ..., "value": ...}`, con el mismo nombre
+/// de tipo que `type_name`. Los `bigint` se escriben como cadena decimal y
+/// las fracciones como `[numerador, denominador]`.
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(tag = "type", content = "value", rename_all = "lowercase")
+)]
 pub enum Value {
     Float(f64),
     Int(i64),
+    Bool(bool),
+    #[cfg_attr(feature = "serde", serde(rename = "string"))]
+    Str(String),
+    /// Entero de precisión arbitraria; solo aparece en modo `bigint`.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::bigint"))]
+    BigInt(BigInt),
+    /// Fracción reducida con denominador distinto de 1; solo aparece en
+    /// modo `rational`.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::rational"))]
+    Rational(Rational64),
+    /// Referencia a un arreglo del heap.
+    Array(HeapRef),
+    /// Referencia a un mapa del heap.
+    Map(HeapRef),
+}
+
+/// Llave de un mapa: solo enteros y cadenas.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(untagged))]
+pub enum MapKey {
+    Int(i64),
+    Str(String),
+}
+
+impl MapKey {
+    fn from_value(val: Value) -> Result<Self, VmErrorKind> {
+        match val {
+            Value::Int(i) => Ok(MapKey::Int(i)),
+            Value::Str(s) => Ok(MapKey::Str(s)),
+            val => Err(VmErrorKind::mismatch("int o string", &val)),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        match self {
+            MapKey::Int(i) => Value::Int(*i),
+            MapKey::Str(s) => Value::Str(s.clone()),
+        }
+    }
+}
+
+impl fmt::Display for MapKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_value().repr())
+    }
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Float(_) => "float",
+            Value::Int(_) => "int",
+            Value::Bool(_) => "bool",
+            Value::Str(_) => "string",
+            Value::BigInt(_) => "bigint",
+            Value::Rational(_) => "rational",
+            Value::Array(_) => "array",
+            Value::Map(_) => "map",
+        }
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Float(f) => Some(*f),
+            Value::Int(i) => Some(*i as f64),
+            Value::BigInt(big) => big.to_f64(),
+            Value::Rational(r) => r.to_f64(),
+            _ => None,
+        }
+    }
+
+    /// Representación para inspeccionar valores: igual que `Display`, pero
+    /// las cadenas se muestran entre comillas. Para ver el contenido de
+    /// arreglos y mapas use `Heap::repr`.
+    pub fn repr(&self) -> String {
+        match self {
+            Value::Str(s) => format!("{:?}", s),
+            val => val.to_string(),
+        }
+    }
+
+    /// Objeto del heap al que apunta el valor, si es un arreglo o un mapa.
+    pub fn heap_ref(&self) -> Option<HeapRef> {
+        match self {
+            Value::Array(r) | Value::Map(r) => Some(*r),
+            _ => None,
+        }
+    }
+
+    /// Igualdad entre valores del mismo tipo (los números se comparan entre
+    /// sí sin importar si son enteros o flotantes). Los arreglos y mapas se
+    /// comparan por identidad; `Heap::equals` compara su contenido.
+    pub fn equals(&self, other: &Value) -> Result<bool, VmErrorKind> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => Ok(a == b),
+            (Value::Bool(a), Value::Bool(b)) => Ok(a == b),
+            (Value::Str(a), Value::Str(b)) => Ok(a == b),
+            (Value::BigInt(a), Value::BigInt(b)) => Ok(a == b),
+            (Value::Rational(a), Value::Rational(b)) => Ok(a == b),
+            (Value::Array(a), Value::Array(b)) => Ok(a == b),
+            (Value::Map(a), Value::Map(b)) => Ok(a == b),
+            // Los BigInt y las fracciones normalizados nunca son iguales a un Int
+            (Value::BigInt(_) | Value::Rational(_), Value::Int(_))
+            | (Value::Int(_), Value::BigInt(_) | Value::Rational(_)) => Ok(false),
+            (a, b) => match (a.as_f64(), b.as_f64()) {
+                (Some(a), Some(b)) => Ok(a == b),
+                _ => Err(VmErrorKind::mismatch(a.type_name(), b)),
+            },
+        }
+    }
+
+    /// Valor de verdad. Sin `truthy` solo se aceptan booleanos; con él, los
+    /// números distintos de cero también cuentan como verdaderos. Los
+    /// arreglos y mapas se evalúan con `Heap::truth`.
+    pub fn truth(&self, truthy: bool) -> Result<bool, VmErrorKind> {
+        match self {
+            Value::Bool(b) => Ok(*b),
+            Value::Str(s) if truthy => Ok(!s.is_empty()),
+            val if truthy => Ok(val.as_f64() != Some(0.0)),
+            val => Err(VmErrorKind::mismatch("bool", val)),
+        }
+    }
+
+    /// Orden numérico, o lexicográfico entre cadenas; `None` si alguno de
+    /// los operandos es NaN.
+    pub fn compare(&self, other: &Value) -> Result<Option<Ordering>, VmErrorKind> {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => Ok(Some(a.cmp(b))),
+            (Value::Str(a), Value::Str(b)) => Ok(Some(a.cmp(b))),
+            (Value::BigInt(a), Value::BigInt(b)) => Ok(Some(a.cmp(b))),
+            (Value::BigInt(a), Value::Int(b)) => Ok(Some(a.cmp(&BigInt::from(*b)))),
+            (Value::Int(a), Value::BigInt(b)) => Ok(Some(BigInt::from(*a).cmp(b))),
+            (Value::Rational(a), Value::Rational(b)) => Ok(Some(a.cmp(b))),
+            (Value::Rational(a), Value::Int(b)) => Ok(Some(a.cmp(&Rational64::from_integer(*b)))),
+            (Value::Int(a), Value::Rational(b)) => Ok(Some(Rational64::from_integer(*a).cmp(b))),
+            (a, b) => match (a.as_f64(), b.as_f64()) {
+                (Some(a), Some(b)) => Ok(a.partial_cmp(&b)),
+                (None, _) => Err(VmErrorKind::mismatch("número", a)),
+                (_, None) => Err(VmErrorKind::mismatch("número", b)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Float(v) => write!(f, "{}", v),
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Bool(v) => write!(f, "{}", v),
+            Value::Str(v) => write!(f, "{}", v),
+            Value::BigInt(v) => write!(f, "{}", v),
+            Value::Rational(v) => write!(f, "{}/{}", v.numer(), v.denom()),
+            Value::Array(r) => write!(f, "<array {}>", r),
+            Value::Map(r) => write!(f, "<map {}>", r),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmErrorKind {
+    StackUnderflow,
+    UndefinedVariable(String),
+    EndOfInput,
+    DivisionByZero,
+    /// El puntero de instrucción quedó fuera del programa.
+    InvalidAddress(usize),
+    Io(String),
+    /// Se superó la profundidad máxima de llamadas configurada.
+    CallStackOverflow(usize),
+    ReturnWithoutCall,
+    /// El operando no es del tipo que la instrucción espera.
+    TypeMismatch {
+        expected: String,
+        found: String,
+    },
+    IndexOutOfBounds {
+        index: i64,
+        len: usize,
+    },
+    /// El resultado de una operación entera no cabe en un `i64`.
+    IntegerOverflow,
+    /// `ARRAY_POP` sobre un arreglo vacío.
+    EmptyArray,
+    /// `MAP_GET` con una llave que no existe en el mapa.
+    KeyNotFound(String),
+    /// `TO_NUM` recibió una cadena que no representa un número.
+    NotANumber(String),
+    /// El heap llegó a su límite de objetos vivos aun después de recolectar.
+    HeapExhausted(usize),
+}
+
+impl VmErrorKind {
+    fn mismatch(expected: &str, found: &Value) -> Self {
+        VmErrorKind::TypeMismatch {
+            expected: expected.to_string(),
+            found: found.type_name().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for VmErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmErrorKind::StackUnderflow => write!(f, "desbordamiento inferior de la pila"),
+            VmErrorKind::UndefinedVariable(name) => write!(f, "variable {} no encontrada", name),
+            VmErrorKind::EndOfInput => write!(f, "se alcanzó el fin de la entrada"),
+            VmErrorKind::DivisionByZero => write!(f, "división entre cero"),
+            VmErrorKind::InvalidAddress(ip) => {
+                write!(f, "dirección de instrucción inválida: {}", ip)
+            }
+            VmErrorKind::Io(msg) => write!(f, "error de E/S: {}", msg),
+            VmErrorKind::CallStackOverflow(depth) => write!(
+                f,
+                "desbordamiento de la pila de llamadas (profundidad máxima {})",
+                depth
+            ),
+            VmErrorKind::ReturnWithoutCall => write!(f, "RET sin un CALL pendiente"),
+            VmErrorKind::TypeMismatch { expected, found } => write!(
+                f,
+                "tipo incorrecto: se esperaba {} pero se encontró {}",
+                expected, found
+            ),
+            VmErrorKind::IndexOutOfBounds { index, len } => {
+                write!(f, "índice {} fuera de rango (longitud {})", index, len)
+            }
+            VmErrorKind::IntegerOverflow => write!(f, "desbordamiento de entero"),
+            VmErrorKind::EmptyArray => write!(f, "el arreglo está vacío"),
+            VmErrorKind::KeyNotFound(key) => write!(f, "la llave {} no existe en el mapa", key),
+            VmErrorKind::NotANumber(s) => write!(f, "{:?} no es un número", s),
+            VmErrorKind::HeapExhausted(limit) => write!(
+                f,
+                "memoria agotada: el heap llegó a su límite de {} objetos",
+                limit
+            ),
+        }
+    }
+}
+
+/// Error de ejecución junto con la instrucción que lo provocó.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VmError {
+    pub kind: VmErrorKind,
+    pub ip: usize,
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(
+                f,
+                "error en la instrucción {} (línea {}): {}",
+                self.ip, line, self.kind
+            ),
+            None => write!(f, "error en la instrucción {}: {}", self.ip, self.kind),
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// Quedan instrucciones por ejecutar.
+    Continue,
+    /// El puntero de instrucción llegó al final del programa.
+    Halted,
+}
+
+/// Opciones de ejecución de la máquina.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VmConfig {
+    /// Número máximo de `CALL` anidados antes de reportar un desbordamiento.
+    pub max_call_depth: usize,
+    /// Permite usar números como condiciones en `AND`, `OR`, `NOT`, `XOR`,
+    /// `JMPT` y `JMPF` (cero es falso).
+    pub truthy: bool,
+    /// Comportamiento ante desbordamientos de la aritmética entera.
+    pub overflow: OverflowMode,
+    /// Promueve a `Value::BigInt` los enteros que no caben en un `i64`, sin
+    /// importar `overflow`.
+    pub bigint: bool,
+    /// `DIV` entre enteros produce una fracción exacta en lugar de un
+    /// flotante.
+    pub rational: bool,
+    /// Número máximo de arreglos y mapas vivos en el heap.
+    pub heap_limit: usize,
+    /// Cuántos pasos recuerda la máquina para poder deshacerlos con
+    /// `step_back`; con 0 no se registra nada. Los objetos del heap que el
+    /// historial puede devolver no se recolectan.
+    pub history_limit: usize,
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        VmConfig {
+            max_call_depth: 1024,
+            truthy: false,
+            overflow: OverflowMode::default(),
+            bigint: false,
+            rational: false,
+            heap_limit: 1 << 20,
+            history_limit: 0,
+        }
+    }
+}
+
+/// Registro de activación creado por `CALL`, con las variables locales
+/// de la subrutina.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Frame {
+    /// Instrucción a la que vuelve `RET`.
+    pub return_addr: usize,
+    /// Dirección de la subrutina llamada.
+    pub function: usize,
+    #[cfg_attr(
+        feature = "serde",
+        serde(serialize_with = "crate::serde_helpers::sorted")
+    )]
+    pub locals: HashMap<String, Value>,
+}
+
+/// Copia del estado observable de la máquina: pila, variables, marcos de
+/// llamada y objetos vivos del heap.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct VmState {
+    pub ip: usize,
+    pub stack: Vec<Value>,
+    pub globals: BTreeMap<String, Value>,
+    pub call_stack: Vec<Frame>,
+    pub heap: Vec<(HeapRef, HeapObject)>,
+}
+
+/// Todo lo necesario para continuar una ejecución: el programa, la
+/// configuración y el estado completo de la máquina, heap incluido.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Snapshot {
+    pub program: Program,
+    pub config: VmConfig,
+    pub ip: usize,
+    pub stack: Vec<Value>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(serialize_with = "crate::serde_helpers::sorted")
+    )]
+    pub vars: HashMap<String, Value>,
+    pub call_stack: Vec<Frame>,
+    pub heap: Heap,
+}
+
+impl Snapshot {
+    /// Verifica que todas las referencias al heap, en la pila, en las
+    /// variables y en los objetos mismos, apunten a objetos vivos.
+    pub fn validate(&self) -> Result<(), String> {
+        self.heap.validate()?;
+        let locals = self
+            .call_stack
+            .iter()
+            .flat_map(|frame| frame.locals.values());
+        self.stack
+            .iter()
+            .chain(self.vars.values())
+            .chain(locals)
+            .try_for_each(|val| self.heap.check(val))
+    }
+}
+
+/// Interpreta el texto como entero o, si no, como flotante. En modo
+/// `bigint` los enteros demasiado grandes se leen sin perder dígitos.
+pub fn parse_number(text: &str, bigint: bool) -> Option<Value> {
+    if let Ok(val) = text.parse::<i64>() {
+        return Some(Value::Int(val));
+    }
+    if bigint {
+        if let Ok(big) = text.parse::<BigInt>() {
+            return Some(Value::BigInt(big));
+        }
+    }
+    text.parse::<f64>().ok().map(Value::Float)
 }
 
 pub struct VM {
     stack: Vec<Value>,
     vars: HashMap<String, Value>,
-    instructions: Vec<Instruction>,
+    call_stack: Vec<Frame>,
+    heap: Heap,
+    history: History,
+    /// Variable que escribió la última instrucción ejecutada.
+    last_write: Option<String>,
+    program: Program,
+    config: VmConfig,
     ip: usize, // Instruction pointer
+    /// De dónde lee `READ`; `None` es la entrada estándar.
+    input: Option<Box<dyn BufRead>>,
+    /// A dónde escribe `PRINT`.
+    output: Box<dyn Write>,
 }
 
 impl VM {
-    pub fn new(instructions: Vec<Instruction>) -> Self {
+    pub fn new(program: Program) -> Self {
+        VM::with_config(program, VmConfig::default())
+    }
+
+    pub fn with_config(program: Program, config: VmConfig) -> Self {
         VM {
             stack: Vec::new(),
             vars: HashMap::new(),
-            instructions,
+            call_stack: Vec::new(),
+            heap: Heap::new(config.heap_limit),
+            history: History::default(),
+            last_write: None,
+            program,
+            config,
             ip: 0,
+            input: None,
+            output: Box::new(io::stdout()),
         }
     }
 
+    /// Hace que `READ` lea de `input` en lugar de la entrada estándar, por
+    /// ejemplo para que el depurador no comparta la terminal con el programa.
+    pub fn set_input<R: BufRead + 'static>(&mut self, input: R) {
+        self.input = Some(Box::new(input));
+    }
+
+    /// Hace que `PRINT` escriba en `output` en lugar de la salida estándar.
+    pub fn set_output<W: Write + 'static>(&mut self, output: W) {
+        self.output = Box::new(output);
+    }
+
+    /// Crea una máquina a partir de un estado guardado. Falla si el estado
+    /// no es consistente, por ejemplo si alguna referencia del heap no
+    /// apunta a un objeto.
+    pub fn from_snapshot(snapshot: Snapshot) -> Result<Self, String> {
+        let mut vm = VM::new(Program::default());
+        vm.restore(snapshot)?;
+        Ok(vm)
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            program: self.program.clone(),
+            config: self.config.clone(),
+            ip: self.ip,
+            stack: self.stack.clone(),
+            vars: self.vars.clone(),
+            call_stack: self.call_stack.clone(),
+            heap: self.heap.clone(),
+        }
+    }
+
+    /// Regresa la máquina al estado guardado en `snapshot`. Si el estado no
+    /// es consistente la máquina no cambia.
+    pub fn restore(&mut self, snapshot: Snapshot) -> Result<(), String> {
+        snapshot.validate()?;
+        self.program = snapshot.program;
+        self.config = snapshot.config;
+        self.ip = snapshot.ip;
+        self.stack = snapshot.stack;
+        self.vars = snapshot.vars;
+        self.call_stack = snapshot.call_stack;
+        self.heap = snapshot.heap;
+        // La configuración pudo cambiar al continuar la ejecución.
+        self.heap.set_limit(self.config.heap_limit);
+        self.history.clear();
+        self.last_write = None;
+        Ok(())
+    }
+
     pub fn current_instruction(&self) -> Option<&Instruction> {
-        self.instructions.get(self.ip)
+        self.program.get(self.ip)
+    }
+
+    /// Línea del archivo fuente de la instrucción actual.
+    pub fn current_location(&self) -> Option<SourceLocation<'_>> {
+        self.program.location(self.ip)
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn config(&self) -> &VmConfig {
+        &self.config
+    }
+
+    /// Nombre de la variable que escribió la última instrucción, si fue un
+    /// `STORE_VAR` o `STORE_GLOBAL`.
+    pub fn last_write(&self) -> Option<&str> {
+        self.last_write.as_deref()
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn call_stack(&self) -> &[Frame] {
+        &self.call_stack
+    }
+
+    pub fn heap(&self) -> &Heap {
+        &self.heap
+    }
+
+    pub fn state(&self) -> VmState {
+        VmState {
+            ip: self.ip,
+            stack: self.stack.clone(),
+            globals: self.vars.clone().into_iter().collect(),
+            call_stack: self.call_stack.clone(),
+            heap: self
+                .heap
+                .iter()
+                .map(|(r, object)| (r, object.clone()))
+                .collect(),
+        }
+    }
+
+    /// Recolecta los objetos del heap que no son alcanzables desde la pila,
+    /// las variables globales ni los marcos de llamada.
+    pub fn collect_garbage(&mut self) -> GcReport {
+        let locals = self
+            .call_stack
+            .iter()
+            .flat_map(|frame| frame.locals.values());
+        let roots = self.stack.iter().chain(self.vars.values()).chain(locals);
+        // Lo que el historial puede devolver también debe seguir vivo.
+        let roots = roots.filter_map(Value::heap_ref).chain(self.history.refs());
+        self.heap.collect(roots)
+    }
+
+    /// Lista los objetos vivos del heap y las estadísticas del recolector.
+    pub fn print_heap(&self) {
+        println!("{:<6} | {:<6} | {:<10}", "Objeto", "Tipo", "Contenido");
+        println!("--------------------------------");
+        for (r, object) in self.heap.iter() {
+            let contents = match object {
+                HeapObject::Array(_) => self.heap.repr(&Value::Array(r)),
+                HeapObject::Map(_) => self.heap.repr(&Value::Map(r)),
+            };
+            println!(
+                "{:<6} | {:<6} | {:<10}",
+                r.to_string(),
+                object.type_name(),
+                contents
+            );
+        }
+        let stats = self.heap.stats();
+        println!(
+            "{} objeto(s) vivo(s) de un máximo de {}; {} recolección(es), {} objeto(s) liberado(s) en total",
+            self.heap.len(),
+            self.heap.limit(),
+            stats.collections,
+            stats.freed
+        );
+    }
+
+    /// Nombre de la subrutina de un marco; `None` es el programa principal.
+    pub fn function_name(&self, frame: Option<&Frame>) -> String {
+        match frame {
+            Some(frame) => self
+                .program
+                .label_at(frame.function)
+                .unwrap_or("<anónima>")
+                .to_string(),
+            None => "<principal>".to_string(),
+        }
+    }
+
+    pub fn print_backtrace(&self) {
+        let mut ip = self.ip;
+        for depth in (0..=self.call_stack.len()).rev() {
+            let frame = depth.checked_sub(1).map(|ix| &self.call_stack[ix]);
+            let line = match self.program.line_of(ip) {
+                Some(line) => format!("línea {}", line),
+                None => "fin del programa".to_string(),
+            };
+            println!(
+                "#{:<3} {:<15} instrucción {} ({})",
+                self.call_stack.len() - depth,
+                self.function_name(frame),
+                ip,
+                line
+            );
+            if let Some(frame) = frame {
+                ip = frame.return_addr - 1;
+            }
+        }
     }
 
     pub fn print_stack(&self) {
         println!("{:<5} | {:<10}", "Index", "Value");
         println!("---------------------");
         for (i, val) in self.stack.iter().enumerate() {
-            match val {
-                Value::Float(f) => println!("{:<5} | {:<10}", i, f),
-                Value::Int(i) => println!("{:<5} | {:<10}", i, i),
-            }
+            self.print_row(&i.to_string(), 5, val);
         }
     }
 
-    pub fn print_vars(&self) {
+    fn print_scope(&self, vars: &HashMap<String, Value>) {
         println!("{:<10} | {:<10}", "Variable", "Value");
         println!("--------------------------");
-        for (name, val) in &self.vars {
-            match val {
-                Value::Float(f) => println!("{:<10} | {:<10}", name, f),
-                Value::Int(i) => println!("{:<10} | {:<10}", name, i),
+        let mut names: Vec<&String> = vars.keys().collect();
+        names.sort();
+        for name in names {
+            self.print_row(name, 10, &vars[name]);
+        }
+    }
+
+    /// Fila de una tabla; los valores de varias líneas se alinean bajo la
+    /// columna de valores.
+    fn print_row(&self, key: &str, width: usize, val: &Value) {
+        for (ix, line) in self.heap.pretty(val).lines().enumerate() {
+            let key = if ix == 0 { key } else { "" };
+            println!("{:<width$} | {:<10}", key, line, width = width);
+        }
+    }
+
+    /// Muestra las variables globales y las locales de cada marco, del más
+    /// externo al más interno.
+    pub fn print_vars(&self) {
+        println!("Globales:");
+        self.print_scope(&self.vars);
+        for (depth, frame) in self.call_stack.iter().enumerate() {
+            println!();
+            println!(
+                "Marco #{} ({}):",
+                depth + 1,
+                self.function_name(Some(frame))
+            );
+            self.print_scope(&frame.locals);
+        }
+    }
+
+    pub fn stack(&self) -> &[Value] {
+        &self.stack
+    }
+
+    pub fn globals(&self) -> &HashMap<String, Value> {
+        &self.vars
+    }
+
+    /// Busca una variable en el marco actual y, si no está, en las globales.
+    pub fn lookup_var(&self, name: &str) -> Option<&Value> {
+        self.call_stack
+            .last()
+            .and_then(|frame| frame.locals.get(name))
+            .or_else(|| self.vars.get(name))
+    }
+
+    /// Cambia el valor de una variable desde fuera del programa. Escribe la
+    /// local del marco actual si existe y, si no, la global. El cambio no
+    /// queda en el historial.
+    pub fn set_var(&mut self, name: &str, val: Value) {
+        let scope = match self.call_stack.last_mut() {
+            Some(frame) if frame.locals.contains_key(name) => &mut frame.locals,
+            _ => &mut self.vars,
+        };
+        scope.insert(name.to_string(), val);
+    }
+
+    fn error(&self, kind: VmErrorKind) -> VmError {
+        VmError {
+            kind,
+            ip: self.ip,
+            line: self.program.line_of(self.ip),
+        }
+    }
+
+    fn pop(&mut self) -> Result<Value, VmErrorKind> {
+        self.stack.pop().ok_or(VmErrorKind::StackUnderflow)
+    }
+
+    /// Valor que está `depth` posiciones debajo del tope, sin sacarlo.
+    fn peek(&self, depth: usize) -> Result<&Value, VmErrorKind> {
+        let len = self.require(depth + 1)?;
+        Ok(&self.stack[len - 1 - depth])
+    }
+
+    fn peek_array(&self, depth: usize) -> Result<HeapRef, VmErrorKind> {
+        match self.peek(depth)? {
+            Value::Array(r) => Ok(*r),
+            val => Err(VmErrorKind::mismatch("array", val)),
+        }
+    }
+
+    fn peek_map(&self, depth: usize) -> Result<HeapRef, VmErrorKind> {
+        match self.peek(depth)? {
+            Value::Map(r) => Ok(*r),
+            val => Err(VmErrorKind::mismatch("map", val)),
+        }
+    }
+
+    fn peek_int(&self, depth: usize) -> Result<i64, VmErrorKind> {
+        match self.peek(depth)? {
+            Value::Int(i) => Ok(*i),
+            val => Err(VmErrorKind::mismatch("int", val)),
+        }
+    }
+
+    fn peek_str(&self, depth: usize) -> Result<&str, VmErrorKind> {
+        match self.peek(depth)? {
+            Value::Str(s) => Ok(s),
+            val => Err(VmErrorKind::mismatch("string", val)),
+        }
+    }
+
+    fn peek_key(&self, depth: usize) -> Result<MapKey, VmErrorKind> {
+        MapKey::from_value(self.peek(depth)?.clone())
+    }
+
+    /// Saca `n` valores de la pila, ya validados con `peek`.
+    fn drop_n(&mut self, n: usize) {
+        self.stack.truncate(self.stack.len() - n);
+    }
+
+    /// Valida un índice contra la longitud de un arreglo.
+    fn index(index: i64, len: usize) -> Result<usize, VmErrorKind> {
+        if index >= 0 && (index as usize) < len {
+            Ok(index as usize)
+        } else {
+            Err(VmErrorKind::IndexOutOfBounds { index, len })
+        }
+    }
+
+    fn peek_truth(&self, depth: usize) -> Result<bool, VmErrorKind> {
+        self.heap.truth(self.peek(depth)?, self.config.truthy)
+    }
+
+    /// Verifica que la pila tenga al menos `n` valores y devuelve su tamaño.
+    fn require(&self, n: usize) -> Result<usize, VmErrorKind> {
+        if self.stack.len() < n {
+            Err(VmErrorKind::StackUnderflow)
+        } else {
+            Ok(self.stack.len())
+        }
+    }
+
+    /// Copia al tope el valor que está `n` posiciones por debajo de él.
+    fn pick(&mut self, n: usize) -> Result<(), VmErrorKind> {
+        let len = self.require(n + 1)?;
+        let val = self.stack[len - 1 - n].clone();
+        self.stack.push(val);
+        Ok(())
+    }
+
+    pub fn step(&mut self) -> Result<StepOutcome, VmError> {
+        let instr = match self.program.get(self.ip) {
+            Some(instr) => instr.clone(),
+            None if self.ip == self.program.len() => return Ok(StepOutcome::Halted),
+            None => return Err(self.error(VmErrorKind::InvalidAddress(self.ip))),
+        };
+
+        // Entre instrucciones todas las referencias vivas están en la pila o
+        // en alguna variable, así que es un punto seguro para recolectar.
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+
+        self.last_write = None;
+        let undo = (self.config.history_limit > 0).then(|| self.undo_entry(&instr));
+        let result = self.execute(&instr);
+        if let Some(mut entry) = undo {
+            if result.is_ok() && history::allocates(&instr) {
+                entry.allocated = self.stack.last().and_then(Value::heap_ref);
             }
+            // También se registran los pasos fallidos, para poder recuperar
+            // los operandos que alcanzaron a sacarse de la pila.
+            self.history.push(entry, self.config.history_limit);
         }
+        match result {
+            Ok(Some(target)) => self.ip = target,
+            Ok(None) => self.ip += 1,
+            Err(kind) => return Err(self.error(kind)),
+        }
+
+        if self.ip < self.program.len() {
+            Ok(StepOutcome::Continue)
+        } else {
+            Ok(StepOutcome::Halted)
+        }
+    }
+
+    /// Registra lo que `instr` está por modificar.
+    fn undo_entry(&self, instr: &Instruction) -> UndoEntry {
+        let len = self.stack.len();
+        let base = len - history::stack_inputs(instr).min(len);
+        let var = match instr {
+            Instruction::StoreVar(name) => Some(match self.call_stack.last() {
+                Some(frame) => VarWrite::Local {
+                    name: name.clone(),
+                    previous: frame.locals.get(name).cloned(),
+                },
+                None => VarWrite::Global {
+                    name: name.clone(),
+                    previous: self.vars.get(name).cloned(),
+                },
+            }),
+            Instruction::StoreGlobal(name) => Some(VarWrite::Global {
+                name: name.clone(),
+                previous: self.vars.get(name).cloned(),
+            }),
+            _ => None,
+        };
+        let frame = match instr {
+            Instruction::Call(_) => Some(FrameChange::Pushed),
+            Instruction::Ret => self.call_stack.last().cloned().map(FrameChange::Popped),
+            _ => None,
+        };
+        let object = match self.stack.get(base) {
+            Some(val) if history::mutates_object(instr) => {
+                val.heap_ref().map(|r| (r, self.heap.get(r).clone()))
+            }
+            _ => None,
+        };
+        UndoEntry {
+            ip: self.ip,
+            base,
+            consumed: self.stack[base..].to_vec(),
+            var,
+            frame,
+            object,
+            allocated: None,
+        }
+    }
+
+    /// Número de pasos que se pueden deshacer.
+    pub fn history_len(&self) -> usize {
+        self.history.len()
     }
 
-    pub fn step(&mut self) {
-        let instr = &self.instructions[self.ip].clone();
+    /// Deshace el último paso registrado. Devuelve `false` si no hay nada
+    /// que deshacer. La salida ya impresa y la entrada ya leída no se
+    /// recuperan.
+    pub fn step_back(&mut self) -> bool {
+        let Some(entry) = self.history.pop() else {
+            return false;
+        };
+        self.stack.truncate(entry.base);
+        self.stack.extend(entry.consumed);
+        match entry.var {
+            Some(VarWrite::Local { name, previous }) => {
+                if let Some(frame) = self.call_stack.last_mut() {
+                    match previous {
+                        Some(val) => frame.locals.insert(name, val),
+                        None => frame.locals.remove(&name),
+                    };
+                }
+            }
+            Some(VarWrite::Global { name, previous }) => {
+                match previous {
+                    Some(val) => self.vars.insert(name, val),
+                    None => self.vars.remove(&name),
+                };
+            }
+            None => {}
+        }
+        match entry.frame {
+            Some(FrameChange::Pushed) => {
+                self.call_stack.pop();
+            }
+            Some(FrameChange::Popped(frame)) => self.call_stack.push(frame),
+            None => {}
+        }
+        if let Some((r, object)) = entry.object {
+            *self.heap.get_mut(r) = object;
+        }
+        if let Some(r) = entry.allocated {
+            self.heap.release(r);
+        }
+        self.ip = entry.ip;
+        self.last_write = None;
+        true
+    }
 
+    /// Ejecuta una instrucción. Devuelve el destino si la instrucción salta.
+    fn execute(&mut self, instr: &Instruction) -> Result<Option<usize>, VmErrorKind> {
         match instr {
             Instruction::LoadConstFloat(val) => self.stack.push(Value::Float(*val)),
             Instruction::LoadConstInt(val) => self.stack.push(Value::Int(*val)),
-            Instruction::LoadVar(name) => {
-                if let Some(val) = self.vars.get(name) {
-                    self.stack.push(val.clone());
-                } else {
-                    panic!("Variable {} not found", name);
-                }
-            }
+            Instruction::LoadConstBool(val) => self.stack.push(Value::Bool(*val)),
+            Instruction::LoadConstStr(val) => self.stack.push(Value::Str(val.clone())),
+            Instruction::LoadVar(name) => match self.lookup_var(name) {
+                Some(val) => self.stack.push(val.clone()),
+                None => return Err(VmErrorKind::UndefinedVariable(name.clone())),
+            },
+            // Dentro de una subrutina STORE_VAR siempre escribe en el marco
+            // actual; las globales se modifican con STORE_GLOBAL.
             Instruction::StoreVar(name) => {
-                if let Some(val) = self.stack.pop() {
-                    self.vars.insert(name.clone(), val);
-                } else {
-                    panic!("Stack underflow");
-                }
+                let val = self.pop()?;
+                match self.call_stack.last_mut() {
+                    Some(frame) => frame.locals.insert(name.clone(), val),
+                    None => self.vars.insert(name.clone(), val),
+                };
+                self.last_write = Some(name.clone());
             }
-            Instruction::Add => self.binary_op(|a, b| a + b),
-            Instruction::Sub => self.binary_op(|a, b| a - b),
-            Instruction::Mul => self.binary_op(|a, b| a * b),
-            Instruction::Div => self.binary_op(|a, b| a / b),
-            Instruction::Pow => self.binary_op(|a, b| a.powf(b)),
-            Instruction::Mod => self.binary_op(|a, b| a % b),
+            Instruction::LoadGlobal(name) => match self.vars.get(name) {
+                Some(val) => self.stack.push(val.clone()),
+                None => return Err(VmErrorKind::UndefinedVariable(name.clone())),
+            },
+            Instruction::StoreGlobal(name) => {
+                let val = self.pop()?;
+                self.vars.insert(name.clone(), val);
+                self.last_write = Some(name.clone());
+            }
+            Instruction::Add => self.binary_op(ArithOp::Add)?,
+            Instruction::Sub => self.binary_op(ArithOp::Sub)?,
+            Instruction::Mul => self.binary_op(ArithOp::Mul)?,
+            Instruction::Div => self.binary_op(ArithOp::Div)?,
+            Instruction::IDiv => self.binary_op(ArithOp::IDiv)?,
+            Instruction::Pow => self.binary_op(ArithOp::Pow)?,
+            Instruction::Mod => self.binary_op(ArithOp::Mod)?,
             Instruction::Print => {
-                if let Some(val) = self.stack.pop() {
-                    match val {
-                        Value::Float(f) => println!("{}", f),
-                        Value::Int(i) => println!("{}", i),
-                    }
-                } else {
-                    panic!("Stack underflow on print");
-                }
+                let val = self.pop()?;
+                writeln!(self.output, "{}", self.heap.display(&val))
+                    .map_err(|err| VmErrorKind::Io(err.to_string()))?;
             }
             Instruction::Read => {
                 let mut input = String::new();
-                println!("Programa solicita entrada: ");
-                io::stdin().read_line(&mut input).expect("Failed to read");
-                if let Ok(val) = input.trim().parse::<f64>() {
-                    self.stack.push(Value::Float(val));
-                } else if let Ok(val) = input.trim().parse::<i64>() {
-                    self.stack.push(Value::Int(val));
-                } else {
-                    panic!("Invalid input");
-                }
-            }
-            Instruction::Jmp(target) => {
-                self.ip = *target;
-                return;
-            }
-            Instruction::JmpEq(target) => {
-                if let Some(cond) = self.stack.pop() {
-                    match cond {
-                        Value::Float(f) if f == 0.0 => {
-                            self.ip = *target;
-                            return;
-                        }
-                        Value::Int(i) if i == 0 => {
-                            self.ip = *target;
-                            return;
-                        }
-                        _ => {}
+                let read = match &mut self.input {
+                    Some(reader) => reader.read_line(&mut input),
+                    None => {
+                        // El aviso no es salida del programa: va a stderr para
+                        // no mezclarse con ella aunque se redirija.
+                        eprintln!("Programa solicita entrada: ");
+                        io::stdin().read_line(&mut input)
                     }
-                } else {
-                    panic!("Stack underflow on conditional jump");
+                };
+                match read {
+                    Ok(0) => return Err(VmErrorKind::EndOfInput),
+                    Ok(_) => {}
+                    Err(err) => return Err(VmErrorKind::Io(err.to_string())),
                 }
+                let input = input.trim_end_matches(['\r', '\n']);
+                let val = parse_number(input.trim(), self.config.bigint)
+                    .unwrap_or_else(|| Value::Str(input.to_string()));
+                self.stack.push(val);
             }
-            Instruction::JmpNe(target) => {
-                if let Some(cond) = self.stack.pop() {
-                    match cond {
-                        Value::Float(f) if f != 0.0 => {
-                            self.ip = *target;
-                            return;
-                        }
-                        Value::Int(i) if i != 0 => {
-                            self.ip = *target;
-                            return;
-                        }
-                        _ => {}
-                    }
-                } else {
-                    panic!("Stack underflow on conditional jump");
-                }
+            Instruction::Jmp(target) => return Ok(Some(*target)),
+            Instruction::JmpEq(target) => return self.jump_if(*target, |x| x == 0.0),
+            Instruction::JmpNe(target) => return self.jump_if(*target, |x| x != 0.0),
+            Instruction::JmpGe(target) => return self.jump_if(*target, |x| x >= 0.0),
+            Instruction::JmpGt(target) => return self.jump_if(*target, |x| x > 0.0),
+            Instruction::JmpLt(target) => return self.jump_if(*target, |x| x < 0.0),
+            Instruction::JmpLe(target) => return self.jump_if(*target, |x| x <= 0.0),
+            Instruction::Dup => self.pick(0)?,
+            Instruction::Pop => {
+                self.pop()?;
             }
-            Instruction::JmpGe(target) => {
-                if let Some(cond) = self.stack.pop() {
-                    match cond {
-                        Value::Float(f) if f >= 0.0 => {
-                            self.ip = *target;
-                            return;
-                        }
-                        Value::Int(i) if i >= 0 => {
-                            self.ip = *target;
-                            return;
-                        }
-                        _ => {}
-                    }
-                } else {
-                    panic!("Stack underflow on conditional jump");
+            Instruction::Swap => {
+                let len = self.require(2)?;
+                self.stack.swap(len - 1, len - 2);
+            }
+            Instruction::Over => self.pick(1)?,
+            // ( a b c -- b c a )
+            Instruction::Rot => {
+                let len = self.require(3)?;
+                self.stack[len - 3..].rotate_left(1);
+            }
+            Instruction::Pick(n) => self.pick(*n)?,
+            Instruction::Eq => self.compare(|heap, a, b| heap.equals(a, b))?,
+            Instruction::Ne => self.compare(|heap, a, b| heap.equals(a, b).map(|eq| !eq))?,
+            Instruction::Lt => self.compare(|_, a, b| Ok(a.compare(b)? == Some(Ordering::Less)))?,
+            Instruction::Le => self.compare(|_, a, b| {
+                Ok(matches!(
+                    a.compare(b)?,
+                    Some(Ordering::Less | Ordering::Equal)
+                ))
+            })?,
+            Instruction::Gt => {
+                self.compare(|_, a, b| Ok(a.compare(b)? == Some(Ordering::Greater)))?
+            }
+            Instruction::Ge => self.compare(|_, a, b| {
+                Ok(matches!(
+                    a.compare(b)?,
+                    Some(Ordering::Greater | Ordering::Equal)
+                ))
+            })?,
+            Instruction::JmpTrue(target) => return self.jump_if_bool(*target, true),
+            Instruction::JmpFalse(target) => return self.jump_if_bool(*target, false),
+            Instruction::And => self.logic_op(|a, b| a && b)?,
+            Instruction::Or => self.logic_op(|a, b| a || b)?,
+            Instruction::Xor => self.logic_op(|a, b| a != b)?,
+            Instruction::Not => {
+                let val = self.peek_truth(0)?;
+                self.drop_n(1);
+                self.stack.push(Value::Bool(!val));
+            }
+            Instruction::Concat => {
+                self.require(2)?;
+                let b = self.peek_str(0)?;
+                let a = self.peek_str(1)?;
+                let joined = format!("{}{}", a, b);
+                self.drop_n(2);
+                self.stack.push(Value::Str(joined));
+            }
+            Instruction::Len => {
+                let len = self.peek_str(0)?.chars().count();
+                self.drop_n(1);
+                self.stack.push(Value::Int(len as i64));
+            }
+            // ( cadena inicio longitud -- subcadena ), en caracteres
+            Instruction::Substr => {
+                self.require(3)?;
+                let len = self.peek_int(0)?;
+                let start = self.peek_int(1)?;
+                let s = self.peek_str(2)?;
+                let total = s.chars().count();
+                let in_range = |ix: i64| ix >= 0 && ix as usize <= total;
+                if !in_range(start) {
+                    return Err(VmErrorKind::IndexOutOfBounds {
+                        index: start,
+                        len: total,
+                    });
+                }
+                // `start + len` puede desbordarse con una longitud enorme.
+                let end = start.checked_add(len);
+                if len < 0 || !end.is_some_and(in_range) {
+                    return Err(VmErrorKind::IndexOutOfBounds {
+                        index: start.saturating_add(len),
+                        len: total,
+                    });
                 }
+                let sub = s.chars().skip(start as usize).take(len as usize).collect();
+                self.drop_n(3);
+                self.stack.push(Value::Str(sub));
             }
-            Instruction::JmpGt(target) => {
-                if let Some(cond) = self.stack.pop() {
-                    match cond {
-                        Value::Float(f) if f > 0.0 => {
-                            self.ip = *target;
-                            return;
-                        }
-                        Value::Int(i) if i > 0 => {
-                            self.ip = *target;
-                            return;
-                        }
-                        _ => {}
-                    }
-                } else {
-                    panic!("Stack underflow on conditional jump");
+            Instruction::ToStr => {
+                let val = self.pop()?;
+                self.stack.push(Value::Str(self.heap.display(&val)));
+            }
+            Instruction::ToNum => {
+                let val = match self.pop()? {
+                    Value::Str(s) => match parse_number(s.trim(), self.config.bigint) {
+                        Some(val) => val,
+                        None => return Err(VmErrorKind::NotANumber(s)),
+                    },
+                    val @ (Value::Int(_)
+                    | Value::Float(_)
+                    | Value::BigInt(_)
+                    | Value::Rational(_)) => val,
+                    val => return Err(VmErrorKind::mismatch("string", &val)),
+                };
+                self.stack.push(val);
+            }
+            Instruction::ToFloat => {
+                let val = self.pop()?;
+                match val.as_f64() {
+                    Some(f) => self.stack.push(Value::Float(f)),
+                    None => return Err(VmErrorKind::mismatch("número", &val)),
                 }
             }
-            Instruction::JmpLt(target) => {
-                if let Some(cond) = self.stack.pop() {
-                    match cond {
-                        Value::Float(f) if f < 0.0 => {
-                            self.ip = *target;
-                            return;
-                        }
-                        Value::Int(i) if i < 0 => {
-                            self.ip = *target;
-                            return;
-                        }
-                        _ => {}
+            Instruction::Num => match self.pop()? {
+                Value::Rational(r) => self.stack.push(Value::Int(*r.numer())),
+                val @ (Value::Int(_) | Value::BigInt(_)) => self.stack.push(val),
+                val => return Err(VmErrorKind::mismatch("rational", &val)),
+            },
+            Instruction::Den => match self.pop()? {
+                Value::Rational(r) => self.stack.push(Value::Int(*r.denom())),
+                Value::Int(_) | Value::BigInt(_) => self.stack.push(Value::Int(1)),
+                val => return Err(VmErrorKind::mismatch("rational", &val)),
+            },
+            // ( v0 ... vn-1 -- arreglo )
+            // Los arreglos y mapas validan sus operandos antes de sacarlos:
+            // si la instrucción falla, la pila queda como estaba.
+            Instruction::NewArray(n) => {
+                let len = self.require(*n)?;
+                let items = self.stack[len - n..].to_vec();
+                let r = self.heap.alloc(HeapObject::Array(items))?;
+                self.drop_n(*n);
+                self.stack.push(Value::Array(r));
+            }
+            // ( arreglo índice -- valor )
+            Instruction::ArrayGet => {
+                self.require(2)?;
+                let index = self.peek_int(0)?;
+                let r = self.peek_array(1)?;
+                let items = self.heap.array(r);
+                let ix = VM::index(index, items.len())?;
+                let val = items[ix].clone();
+                self.drop_n(2);
+                self.stack.push(val);
+            }
+            // ( arreglo índice valor -- arreglo ), modifica el arreglo
+            Instruction::ArraySet => {
+                self.require(3)?;
+                let index = self.peek_int(1)?;
+                let r = self.peek_array(2)?;
+                let ix = VM::index(index, self.heap.array(r).len())?;
+                let val = self.pop()?;
+                self.drop_n(1);
+                self.heap.array_mut(r)[ix] = val;
+            }
+            Instruction::ArrayLen => {
+                let r = self.peek_array(0)?;
+                let len = self.heap.array(r).len();
+                self.drop_n(1);
+                self.stack.push(Value::Int(len as i64));
+            }
+            // ( arreglo valor -- arreglo ), modifica el arreglo
+            Instruction::ArrayPush => {
+                self.require(2)?;
+                let r = self.peek_array(1)?;
+                let val = self.pop()?;
+                self.heap.array_mut(r).push(val);
+            }
+            // ( arreglo -- arreglo valor ), modifica el arreglo
+            Instruction::ArrayPop => {
+                let r = self.peek_array(0)?;
+                let val = self.heap.array_mut(r).pop();
+                self.stack.push(val.ok_or(VmErrorKind::EmptyArray)?);
+            }
+            Instruction::NewMap => {
+                let r = self.heap.alloc(HeapObject::Map(BTreeMap::new()))?;
+                self.stack.push(Value::Map(r));
+            }
+            // ( mapa llave -- valor )
+            Instruction::MapGet => {
+                self.require(2)?;
+                let key = self.peek_key(0)?;
+                let r = self.peek_map(1)?;
+                match self.heap.map(r).get(&key) {
+                    Some(val) => {
+                        let val = val.clone();
+                        self.drop_n(2);
+                        self.stack.push(val);
                     }
-                } else {
-                    panic!("Stack underflow on conditional jump");
+                    None => return Err(VmErrorKind::KeyNotFound(key.to_string())),
                 }
             }
-            Instruction::JmpLe(target) => {
-                if let Some(cond) = self.stack.pop() {
-                    match cond {
-                        Value::Float(f) if f <= 0.0 => {
-                            self.ip = *target;
-                            return;
-                        }
-                        Value::Int(i) if i <= 0 => {
-                            self.ip = *target;
-                            return;
-                        }
-                        _ => {}
-                    }
-                } else {
-                    panic!("Stack underflow on conditional jump");
+            // ( mapa llave valor -- mapa ), modifica el mapa
+            Instruction::MapSet => {
+                self.require(3)?;
+                let key = self.peek_key(1)?;
+                let r = self.peek_map(2)?;
+                let val = self.pop()?;
+                self.drop_n(1);
+                self.heap.map_mut(r).insert(key, val);
+            }
+            // ( mapa llave -- bool )
+            Instruction::MapHas => {
+                self.require(2)?;
+                let key = self.peek_key(0)?;
+                let r = self.peek_map(1)?;
+                let has = self.heap.map(r).contains_key(&key);
+                self.drop_n(2);
+                self.stack.push(Value::Bool(has));
+            }
+            // ( mapa llave -- mapa ), modifica el mapa; borrar una llave
+            // ausente no hace nada
+            Instruction::MapDel => {
+                self.require(2)?;
+                let key = self.peek_key(0)?;
+                let r = self.peek_map(1)?;
+                self.drop_n(1);
+                self.heap.map_mut(r).remove(&key);
+            }
+            // ( mapa -- arreglo ), llaves en orden
+            Instruction::MapKeys => {
+                let r = self.peek_map(0)?;
+                let keys = self.heap.map(r).keys().map(MapKey::to_value);
+                let keys = HeapObject::Array(keys.collect());
+                let r = self.heap.alloc(keys)?;
+                self.drop_n(1);
+                self.stack.push(Value::Array(r));
+            }
+            Instruction::Call(target) => {
+                if self.call_stack.len() >= self.config.max_call_depth {
+                    return Err(VmErrorKind::CallStackOverflow(self.config.max_call_depth));
                 }
+                self.call_stack.push(Frame {
+                    return_addr: self.ip + 1,
+                    function: *target,
+                    locals: HashMap::new(),
+                });
+                return Ok(Some(*target));
             }
+            Instruction::Ret => match self.call_stack.pop() {
+                Some(frame) => return Ok(Some(frame.return_addr)),
+                None => return Err(VmErrorKind::ReturnWithoutCall),
+            },
         }
-        self.ip += 1;
+        Ok(None)
     }
 
-    pub fn run(&mut self) {
-        while self.ip < self.instructions.len() {
-            self.step();
+    /// Saca el tope de la pila y salta a `target` si cumple la condición
+    /// (todos los saltos condicionales comparan contra cero).
+    fn jump_if<F>(&mut self, target: usize, cond: F) -> Result<Option<usize>, VmErrorKind>
+    where
+        F: Fn(f64) -> bool,
+    {
+        let val = self.pop()?;
+        match val.as_f64() {
+            Some(x) if cond(x) => Ok(Some(target)),
+            Some(_) => Ok(None),
+            None => Err(VmErrorKind::mismatch("número", &val)),
         }
     }
 
-    pub fn binary_op<F>(&mut self, op: F)
+    /// Saca un booleano y salta a `target` si es igual a `expected`.
+    fn jump_if_bool(
+        &mut self,
+        target: usize,
+        expected: bool,
+    ) -> Result<Option<usize>, VmErrorKind> {
+        let val = self.peek_truth(0)?;
+        self.drop_n(1);
+        if val == expected {
+            Ok(Some(target))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Revisa los dos operandos antes de sacarlos.
+    fn logic_op<F>(&mut self, op: F) -> Result<(), VmErrorKind>
     where
-        F: Fn(f64, f64) -> f64,
+        F: Fn(bool, bool) -> bool,
     {
-        if let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) {
-            let result = match (a, b) {
-                (Value::Float(a), Value::Float(b)) => Value::Float(op(a, b)),
-                (Value::Int(a), Value::Int(b)) => Value::Int(op(a as f64, b as f64) as i64),
-                (Value::Float(a), Value::Int(b)) => Value::Float(op(a, b as f64)),
-                (Value::Int(a), Value::Float(b)) => Value::Float(op(a as f64, b)),
-            };
-            self.stack.push(result);
-        } else {
-            panic!("Stack underflow on binary operation");
+        self.require(2)?;
+        let b = self.peek_truth(0)?;
+        let a = self.peek_truth(1)?;
+        self.drop_n(2);
+        self.stack.push(Value::Bool(op(a, b)));
+        Ok(())
+    }
+
+    /// Saca dos operandos y apila el resultado booleano de compararlos.
+    fn compare<F>(&mut self, op: F) -> Result<(), VmErrorKind>
+    where
+        F: Fn(&Heap, &Value, &Value) -> Result<bool, VmErrorKind>,
+    {
+        self.require(2)?;
+        let b = self.pop()?;
+        let a = self.pop()?;
+        self.stack.push(Value::Bool(op(&self.heap, &a, &b)?));
+        Ok(())
+    }
+
+    pub fn run(&mut self) -> Result<StepOutcome, VmError> {
+        self.run_until(|_| false)
+    }
+
+    /// Ejecuta hasta que el programa termine o hasta que `stop`, que se
+    /// consulta después de cada instrucción, devuelva `true`. En ese caso
+    /// el resultado es `Continue`.
+    pub fn run_until<F>(&mut self, mut stop: F) -> Result<StepOutcome, VmError>
+    where
+        F: FnMut(&VM) -> bool,
+    {
+        loop {
+            if self.step()? == StepOutcome::Halted {
+                return Ok(StepOutcome::Halted);
+            }
+            if stop(self) {
+                return Ok(StepOutcome::Continue);
+            }
+        }
+    }
+
+    /// Ejecuta a lo más `steps` instrucciones. Devuelve `Continue` si el
+    /// programa no terminó dentro de ese límite.
+    pub fn run_steps(&mut self, steps: u64) -> Result<StepOutcome, VmError> {
+        for _ in 0..steps {
+            if self.step()? == StepOutcome::Halted {
+                return Ok(StepOutcome::Halted);
+            }
+        }
+        match self.program.get(self.ip) {
+            Some(_) => Ok(StepOutcome::Continue),
+            None => Ok(StepOutcome::Halted),
         }
     }
+
+    pub fn binary_op(&mut self, op: ArithOp) -> Result<(), VmErrorKind> {
+        self.require(2)?;
+        let b = self.pop()?;
+        let a = self.pop()?;
+        let result = arith::binary(op, a, b, &self.config)?;
+        self.stack.push(result);
+        Ok(())
+    }
 }
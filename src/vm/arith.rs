@@ -0,0 +1,327 @@
+use super::{Value, VmConfig, VmErrorKind};
+use num_bigint::BigInt;
+use num_rational::Rational64;
+use num_traits::{CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, ToPrimitive};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Qué hacer cuando una operación entre enteros no cabe en un `i64`.
+///
+/// Con fracciones solo `Promote` cambia algo: el resto de los modos reportan
+/// el desbordamiento.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum OverflowMode {
+    /// Reportar `VmErrorKind::IntegerOverflow`.
+    #[default]
+    Error,
+    /// Aritmética modular en complemento a dos.
+    Wrap,
+    /// Quedarse en `i64::MIN` o `i64::MAX`.
+    Saturate,
+    /// Repetir la operación en punto flotante.
+    Promote,
+}
+
+impl FromStr for OverflowMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(OverflowMode::Error),
+            "wrap" => Ok(OverflowMode::Wrap),
+            "saturate" => Ok(OverflowMode::Saturate),
+            "promote" => Ok(OverflowMode::Promote),
+            _ => Err(format!(
+                "modo de desbordamiento desconocido: {} (use error, wrap, saturate o promote)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OverflowMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OverflowMode::Error => "error",
+            OverflowMode::Wrap => "wrap",
+            OverflowMode::Saturate => "saturate",
+            OverflowMode::Promote => "promote",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    /// División real: siempre produce un flotante.
+    Div,
+    /// División entera truncada hacia cero.
+    IDiv,
+    Mod,
+    Pow,
+}
+
+impl ArithOp {
+    fn float(self, a: f64, b: f64) -> f64 {
+        match self {
+            ArithOp::Add => a + b,
+            ArithOp::Sub => a - b,
+            ArithOp::Mul => a * b,
+            ArithOp::Div => a / b,
+            ArithOp::IDiv => (a / b).trunc(),
+            ArithOp::Mod => a % b,
+            ArithOp::Pow => a.powf(b),
+        }
+    }
+
+    fn checked(self, a: i64, b: i64) -> Option<i64> {
+        match self {
+            ArithOp::Add => a.checked_add(b),
+            ArithOp::Sub => a.checked_sub(b),
+            ArithOp::Mul => a.checked_mul(b),
+            ArithOp::IDiv => a.checked_div(b),
+            ArithOp::Mod => a.checked_rem(b),
+            ArithOp::Pow => u64::try_from(b)
+                .ok()
+                .and_then(|b| trivial_pow(a, b))
+                .or_else(|| u32::try_from(b).ok().and_then(|b| a.checked_pow(b))),
+            ArithOp::Div => None,
+        }
+    }
+
+    fn wrapping(self, a: i64, b: i64) -> i64 {
+        match self {
+            ArithOp::Add => a.wrapping_add(b),
+            ArithOp::Sub => a.wrapping_sub(b),
+            ArithOp::Mul => a.wrapping_mul(b),
+            ArithOp::IDiv => a.wrapping_div(b),
+            ArithOp::Mod => a.wrapping_rem(b),
+            ArithOp::Pow => wrapping_pow(a, b.max(0) as u64),
+            ArithOp::Div => unreachable!("DIV siempre se resuelve en punto flotante"),
+        }
+    }
+
+    fn saturating(self, a: i64, b: i64) -> i64 {
+        match self {
+            ArithOp::Add => a.saturating_add(b),
+            ArithOp::Sub => a.saturating_sub(b),
+            ArithOp::Mul => a.saturating_mul(b),
+            ArithOp::IDiv => a.saturating_div(b),
+            ArithOp::Mod => a.checked_rem(b).unwrap_or(0),
+            ArithOp::Pow => trivial_pow(a, b.max(0) as u64).unwrap_or_else(|| {
+                match u32::try_from(b) {
+                    Ok(b) => a.saturating_pow(b),
+                    // Con |a| >= 2 un exponente tan grande siempre se desborda.
+                    Err(_) if a < 0 && b % 2 == 1 => i64::MIN,
+                    Err(_) => i64::MAX,
+                }
+            }),
+            ArithOp::Div => unreachable!("DIV siempre se resuelve en punto flotante"),
+        }
+    }
+
+    fn is_division(self) -> bool {
+        matches!(self, ArithOp::Div | ArithOp::IDiv | ArithOp::Mod)
+    }
+}
+
+/// Potencias de 0, 1 y -1, que nunca se desbordan sin importar el tamaño
+/// del exponente.
+fn trivial_pow(a: i64, b: u64) -> Option<i64> {
+    match a {
+        0 if b == 0 => Some(1),
+        0 => Some(0),
+        1 => Some(1),
+        -1 if b.is_multiple_of(2) => Some(1),
+        -1 => Some(-1),
+        _ => None,
+    }
+}
+
+/// Potencia módulo 2^64 por cuadrados sucesivos, con exponentes de
+/// cualquier tamaño.
+fn wrapping_pow(mut a: i64, mut b: u64) -> i64 {
+    let mut result: i64 = 1;
+    while b > 0 {
+        if b & 1 == 1 {
+            result = result.wrapping_mul(a);
+        }
+        a = a.wrapping_mul(a);
+        b >>= 1;
+    }
+    result
+}
+
+/// Reduce un entero grande a `Value::Int` si cabe en un `i64`.
+pub fn normalize(big: BigInt) -> Value {
+    match big.to_i64() {
+        Some(val) => Value::Int(val),
+        None => Value::BigInt(big),
+    }
+}
+
+fn to_big(val: &Value) -> Option<BigInt> {
+    match val {
+        Value::Int(i) => Some(BigInt::from(*i)),
+        Value::BigInt(big) => Some(big.clone()),
+        _ => None,
+    }
+}
+
+/// Aritmética de precisión arbitraria; el resultado se normaliza a `Int`
+/// cuando es posible.
+fn big_op(op: ArithOp, a: BigInt, b: BigInt) -> Result<Value, VmErrorKind> {
+    let result = match op {
+        ArithOp::Add => a + b,
+        ArithOp::Sub => a - b,
+        ArithOp::Mul => a * b,
+        ArithOp::IDiv => a / b,
+        ArithOp::Mod => a % b,
+        ArithOp::Pow => match b.to_u32() {
+            Some(exp) => a.pow(exp),
+            None if b.sign() == num_bigint::Sign::Minus => {
+                return Ok(Value::Float(op.float(to_f64(&a), to_f64(&b))))
+            }
+            None => return Err(VmErrorKind::IntegerOverflow),
+        },
+        ArithOp::Div => return Ok(Value::Float(op.float(to_f64(&a), to_f64(&b)))),
+    };
+    Ok(normalize(result))
+}
+
+fn to_f64(big: &BigInt) -> f64 {
+    big.to_f64().unwrap_or(f64::NAN)
+}
+
+/// Reduce una fracción a `Value::Int` si su denominador es 1.
+pub fn normalize_ratio(ratio: Rational64) -> Value {
+    if ratio.is_integer() {
+        Value::Int(ratio.to_integer())
+    } else {
+        Value::Rational(ratio)
+    }
+}
+
+fn to_ratio(val: &Value) -> Option<Rational64> {
+    match val {
+        Value::Int(i) => Some(Rational64::from_integer(*i)),
+        Value::Rational(r) => Some(*r),
+        _ => None,
+    }
+}
+
+fn ratio_pow(base: Rational64, exp: Rational64) -> Option<Rational64> {
+    if !exp.is_integer() {
+        return None;
+    }
+    let exp = exp.to_integer();
+    if base.is_integer() {
+        if let Some(val) = trivial_pow(base.to_integer(), exp.unsigned_abs()) {
+            return Some(Rational64::from_integer(val));
+        }
+    }
+    let n = u32::try_from(exp.unsigned_abs()).ok()?;
+    let numer = base.numer().checked_pow(n)?;
+    let denom = base.denom().checked_pow(n)?;
+    if exp >= 0 {
+        Some(Rational64::new(numer, denom))
+    } else {
+        Some(Rational64::new(denom, numer))
+    }
+}
+
+/// Aritmética exacta con fracciones. Si el resultado no es representable
+/// (exponente fraccionario o desbordamiento) se recurre a `f64` cuando
+/// el modo lo permite.
+fn ratio_op(
+    op: ArithOp,
+    a: Rational64,
+    b: Rational64,
+    config: &VmConfig,
+) -> Result<Value, VmErrorKind> {
+    let result = match op {
+        ArithOp::Add => a.checked_add(&b),
+        ArithOp::Sub => a.checked_sub(&b),
+        ArithOp::Mul => a.checked_mul(&b),
+        ArithOp::Div => a.checked_div(&b),
+        ArithOp::IDiv => a.checked_div(&b).map(|q| q.trunc()),
+        ArithOp::Mod => a
+            .checked_div(&b)
+            .and_then(|q| b.checked_mul(&q.trunc()))
+            .and_then(|p| a.checked_sub(&p)),
+        ArithOp::Pow if !b.is_integer() => {
+            return Ok(Value::Float(op.float(ratio_f64(a), ratio_f64(b))))
+        }
+        ArithOp::Pow if *a.numer() == 0 && b < Rational64::from_integer(0) => {
+            return Err(VmErrorKind::DivisionByZero)
+        }
+        ArithOp::Pow => ratio_pow(a, b),
+    };
+    match result {
+        Some(ratio) => Ok(normalize_ratio(ratio)),
+        None if config.overflow == OverflowMode::Promote => {
+            Ok(Value::Float(op.float(ratio_f64(a), ratio_f64(b))))
+        }
+        None => Err(VmErrorKind::IntegerOverflow),
+    }
+}
+
+fn ratio_f64(ratio: Rational64) -> f64 {
+    *ratio.numer() as f64 / *ratio.denom() as f64
+}
+
+/// Aplica `op` a dos enteros sin pasar por `f64` salvo cuando el resultado
+/// no es entero (`DIV`, potencias negativas) o el modo lo pide.
+fn int_op(op: ArithOp, a: i64, b: i64, config: &VmConfig) -> Result<Value, VmErrorKind> {
+    let exact = op == ArithOp::Div || (op == ArithOp::Pow && b < 0);
+    if config.rational && exact {
+        let (a, b) = (Rational64::from_integer(a), Rational64::from_integer(b));
+        return ratio_op(op, a, b, config);
+    }
+    if exact {
+        return Ok(Value::Float(op.float(a as f64, b as f64)));
+    }
+    if let Some(result) = op.checked(a, b) {
+        return Ok(Value::Int(result));
+    }
+    if config.bigint {
+        return big_op(op, BigInt::from(a), BigInt::from(b));
+    }
+    match config.overflow {
+        OverflowMode::Error => Err(VmErrorKind::IntegerOverflow),
+        OverflowMode::Wrap => Ok(Value::Int(op.wrapping(a, b))),
+        OverflowMode::Saturate => Ok(Value::Int(op.saturating(a, b))),
+        OverflowMode::Promote => Ok(Value::Float(op.float(a as f64, b as f64))),
+    }
+}
+
+/// Operación aritmética binaria entre dos valores numéricos.
+pub fn binary(op: ArithOp, a: Value, b: Value, config: &VmConfig) -> Result<Value, VmErrorKind> {
+    if op.is_division() && b.as_f64() == Some(0.0) {
+        return Err(VmErrorKind::DivisionByZero);
+    }
+    if let (Value::Int(x), Value::Int(y)) = (&a, &b) {
+        return int_op(op, *x, *y, config);
+    }
+    if let (Some(x), Some(y)) = (to_ratio(&a), to_ratio(&b)) {
+        return ratio_op(op, x, y, config);
+    }
+    if let (Some(x), Some(y)) = (to_big(&a), to_big(&b)) {
+        return big_op(op, x, y);
+    }
+    match (a.as_f64(), b.as_f64()) {
+        (Some(x), Some(y)) => Ok(Value::Float(op.float(x, y))),
+        (None, _) => Err(VmErrorKind::mismatch("número", &a)),
+        (_, None) => Err(VmErrorKind::mismatch("número", &b)),
+    }
+}
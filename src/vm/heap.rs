@@ -0,0 +1,450 @@
+use super::{MapKey, Value, VmErrorKind};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Umbral inicial de objetos vivos a partir del cual corre el recolector.
+const INITIAL_THRESHOLD: usize = 256;
+
+/// Referencia a un objeto del heap. Copiarla no copia el objeto: dos
+/// referencias iguales apuntan al mismo arreglo o mapa.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
+pub struct HeapRef(usize);
+
+impl HeapRef {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+impl fmt::Display for HeapRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Valor compuesto guardado en el heap. En JSON usa la misma forma que
+/// `Value`; los mapas se escriben como lista de pares `[llave, valor]`.
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(tag = "type", content = "value", rename_all = "lowercase")
+)]
+pub enum HeapObject {
+    Array(Vec<Value>),
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_helpers::entries"))]
+    Map(BTreeMap<MapKey, Value>),
+}
+
+impl HeapObject {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            HeapObject::Array(_) => "array",
+            HeapObject::Map(_) => "map",
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            HeapObject::Array(items) => items.len(),
+            HeapObject::Map(entries) => entries.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn children(&self) -> Box<dyn Iterator<Item = &Value> + '_> {
+        match self {
+            HeapObject::Array(items) => Box::new(items.iter()),
+            HeapObject::Map(entries) => Box::new(entries.values()),
+        }
+    }
+}
+
+/// Resultado de un ciclo de recolección.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcReport {
+    /// Objetos liberados en este ciclo.
+    pub freed: usize,
+    /// Objetos que siguen vivos.
+    pub live: usize,
+}
+
+/// Estadísticas acumuladas del heap.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct GcStats {
+    pub collections: usize,
+    pub allocated: usize,
+    pub freed: usize,
+}
+
+/// Montículo de arreglos y mapas con recolección de basura por marcado y
+/// barrido. Las raíces las proporciona la máquina (pila, variables y
+/// marcos de llamada).
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Heap {
+    slots: Vec<Option<HeapObject>>,
+    free: Vec<usize>,
+    live: usize,
+    limit: usize,
+    next_gc: usize,
+    stats: GcStats,
+}
+
+impl Heap {
+    /// Crea un heap que admite a lo más `limit` objetos vivos.
+    pub fn new(limit: usize) -> Self {
+        Heap {
+            slots: Vec::new(),
+            free: Vec::new(),
+            live: 0,
+            limit,
+            next_gc: INITIAL_THRESHOLD.min(limit),
+            stats: GcStats::default(),
+        }
+    }
+
+    /// Número de objetos vivos (incluida la basura aún no recolectada).
+    pub fn len(&self) -> usize {
+        self.live
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.live == 0
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Cambia el máximo de objetos vivos. Los que ya existen se conservan
+    /// aunque lo rebasen; solo se rechazan las nuevas reservas.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+        self.next_gc = self.next_gc.min(limit);
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    /// Indica si ya se alcanzó el umbral para el siguiente ciclo.
+    pub fn should_collect(&self) -> bool {
+        self.live >= self.next_gc
+    }
+
+    pub fn alloc(&mut self, object: HeapObject) -> Result<HeapRef, VmErrorKind> {
+        if self.live >= self.limit {
+            return Err(VmErrorKind::HeapExhausted(self.limit));
+        }
+        self.live += 1;
+        self.stats.allocated += 1;
+        match self.free.pop() {
+            Some(ix) => {
+                self.slots[ix] = Some(object);
+                Ok(HeapRef(ix))
+            }
+            None => {
+                self.slots.push(Some(object));
+                Ok(HeapRef(self.slots.len() - 1))
+            }
+        }
+    }
+
+    pub fn get(&self, r: HeapRef) -> &HeapObject {
+        self.slots[r.0]
+            .as_ref()
+            .expect("referencia a un objeto ya liberado")
+    }
+
+    pub fn get_mut(&mut self, r: HeapRef) -> &mut HeapObject {
+        self.slots[r.0]
+            .as_mut()
+            .expect("referencia a un objeto ya liberado")
+    }
+
+    pub fn array(&self, r: HeapRef) -> &Vec<Value> {
+        match self.get(r) {
+            HeapObject::Array(items) => items,
+            HeapObject::Map(_) => unreachable!("{} no es un arreglo", r),
+        }
+    }
+
+    pub fn array_mut(&mut self, r: HeapRef) -> &mut Vec<Value> {
+        match self.get_mut(r) {
+            HeapObject::Array(items) => items,
+            HeapObject::Map(_) => unreachable!("{} no es un arreglo", r),
+        }
+    }
+
+    pub fn map(&self, r: HeapRef) -> &BTreeMap<MapKey, Value> {
+        match self.get(r) {
+            HeapObject::Map(entries) => entries,
+            HeapObject::Array(_) => unreachable!("{} no es un mapa", r),
+        }
+    }
+
+    pub fn map_mut(&mut self, r: HeapRef) -> &mut BTreeMap<MapKey, Value> {
+        match self.get_mut(r) {
+            HeapObject::Map(entries) => entries,
+            HeapObject::Array(_) => unreachable!("{} no es un mapa", r),
+        }
+    }
+
+    /// Objetos vivos en orden de dirección.
+    pub fn iter(&self) -> impl Iterator<Item = (HeapRef, &HeapObject)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter_map(|(ix, slot)| slot.as_ref().map(|object| (HeapRef(ix), object)))
+    }
+
+    /// Verifica que `val`, si es un arreglo o un mapa, apunte a un objeto
+    /// vivo del tipo correcto.
+    pub fn check(&self, val: &Value) -> Result<(), String> {
+        let (r, expected) = match val {
+            Value::Array(r) => (*r, "array"),
+            Value::Map(r) => (*r, "map"),
+            _ => return Ok(()),
+        };
+        match self.slots.get(r.0).and_then(Option::as_ref) {
+            None => Err(format!(
+                "la referencia {} apunta a un objeto que no existe",
+                r
+            )),
+            Some(object) if object.type_name() != expected => Err(format!(
+                "la referencia {} debía apuntar a un {} y apunta a un {}",
+                r,
+                expected,
+                object.type_name()
+            )),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Revisa la consistencia de un heap que viene de fuera (por ejemplo,
+    /// de un estado guardado): la lista de lugares libres, el conteo de
+    /// objetos vivos y las referencias dentro de cada objeto.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut free = vec![false; self.slots.len()];
+        for &ix in &self.free {
+            match self.slots.get(ix) {
+                Some(None) if !free[ix] => free[ix] = true,
+                _ => return Err(format!("lugar libre inválido en el heap: {}", ix)),
+            }
+        }
+        let live = self.slots.iter().filter(|slot| slot.is_some()).count();
+        if live != self.live {
+            return Err(format!(
+                "el heap dice tener {} objeto(s) vivo(s) pero tiene {}",
+                self.live, live
+            ));
+        }
+        for (_, object) in self.iter() {
+            for child in object.children() {
+                self.check(child)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Libera un objeto de inmediato, sin esperar al recolector.
+    pub(super) fn release(&mut self, r: HeapRef) {
+        if self.slots[r.0].take().is_some() {
+            self.free.push(r.0);
+            self.live -= 1;
+        }
+    }
+
+    /// Marca todo lo alcanzable desde `roots` y libera el resto.
+    pub fn collect<I>(&mut self, roots: I) -> GcReport
+    where
+        I: IntoIterator<Item = HeapRef>,
+    {
+        let mut marked = vec![false; self.slots.len()];
+        let mut pending: Vec<HeapRef> = roots.into_iter().collect();
+        while let Some(r) = pending.pop() {
+            if std::mem::replace(&mut marked[r.0], true) {
+                continue;
+            }
+            pending.extend(self.get(r).children().filter_map(Value::heap_ref));
+        }
+
+        let mut freed = 0;
+        for (ix, slot) in self.slots.iter_mut().enumerate() {
+            if slot.is_some() && !marked[ix] {
+                *slot = None;
+                self.free.push(ix);
+                freed += 1;
+            }
+        }
+        self.live -= freed;
+        self.next_gc = (self.live * 2).max(INITIAL_THRESHOLD).min(self.limit);
+        self.stats.collections += 1;
+        self.stats.freed += freed;
+        GcReport {
+            freed,
+            live: self.live,
+        }
+    }
+
+    /// Texto que muestra `PRINT`: las cadenas van sin comillas.
+    pub fn display(&self, val: &Value) -> String {
+        match val {
+            Value::Str(s) => s.clone(),
+            val => self.repr(val),
+        }
+    }
+
+    /// Representación de un valor con el contenido de los arreglos y mapas
+    /// a los que hace referencia. Los ciclos se muestran como `[...]`.
+    pub fn repr(&self, val: &Value) -> String {
+        let mut out = String::new();
+        self.write_repr(val, &mut out, &mut Vec::new());
+        out
+    }
+
+    fn write_repr(&self, val: &Value, out: &mut String, seen: &mut Vec<HeapRef>) {
+        let r = match val {
+            Value::Array(r) | Value::Map(r) => *r,
+            val => return out.push_str(&val.repr()),
+        };
+        if seen.contains(&r) {
+            return out.push_str(cycle_marker(val));
+        }
+        seen.push(r);
+        match self.get(r) {
+            HeapObject::Array(items) => {
+                out.push('[');
+                for (ix, item) in items.iter().enumerate() {
+                    if ix > 0 {
+                        out.push_str(", ");
+                    }
+                    self.write_repr(item, out, seen);
+                }
+                out.push(']');
+            }
+            HeapObject::Map(entries) => {
+                out.push('{');
+                for (ix, (key, item)) in entries.iter().enumerate() {
+                    if ix > 0 {
+                        out.push_str(", ");
+                    }
+                    out.push_str(&format!("{}: ", key));
+                    self.write_repr(item, out, seen);
+                }
+                out.push('}');
+            }
+        }
+        seen.pop();
+    }
+
+    /// Como `repr`, pero los arreglos y mapas que no caben en una línea se
+    /// muestran con un elemento por renglón.
+    pub fn pretty(&self, val: &Value) -> String {
+        self.pretty_in(val, &mut Vec::new())
+    }
+
+    fn pretty_in(&self, val: &Value, seen: &mut Vec<HeapRef>) -> String {
+        const MAX_WIDTH: usize = 60;
+        let r = match val {
+            Value::Array(r) | Value::Map(r) => *r,
+            val => return val.repr(),
+        };
+        if seen.contains(&r) {
+            return cycle_marker(val).to_string();
+        }
+        let repr = self.repr(val);
+        if repr.chars().count() <= MAX_WIDTH {
+            return repr;
+        }
+        seen.push(r);
+        let (open, close, entries) = match self.get(r) {
+            HeapObject::Array(items) => {
+                let entries = items.iter().map(|item| self.pretty_in(item, seen));
+                ("[", "]", entries.collect::<Vec<_>>())
+            }
+            HeapObject::Map(entries) => {
+                let entries = entries
+                    .iter()
+                    .map(|(key, item)| format!("{}: {}", key, self.pretty_in(item, seen)));
+                ("{", "}", entries.collect())
+            }
+        };
+        seen.pop();
+        let mut out = format!("{}\n", open);
+        for entry in entries {
+            for line in entry.lines() {
+                out.push_str("  ");
+                out.push_str(line);
+                out.push('\n');
+            }
+            out.insert(out.len() - 1, ',');
+        }
+        out.push_str(close);
+        out
+    }
+
+    /// Igualdad estructural: dos arreglos (o mapas) son iguales si tienen
+    /// los mismos elementos, aunque sean objetos distintos.
+    pub fn equals(&self, a: &Value, b: &Value) -> Result<bool, VmErrorKind> {
+        self.equals_in(a, b, &mut Vec::new())
+    }
+
+    fn equals_in(
+        &self,
+        a: &Value,
+        b: &Value,
+        seen: &mut Vec<(HeapRef, HeapRef)>,
+    ) -> Result<bool, VmErrorKind> {
+        let (ra, rb) = match (a, b) {
+            (Value::Array(ra), Value::Array(rb)) | (Value::Map(ra), Value::Map(rb)) => (*ra, *rb),
+            (a, b) => return a.equals(b),
+        };
+        // Un par que ya se está comparando se supone igual; así terminan
+        // las comparaciones entre estructuras cíclicas.
+        if ra == rb || seen.contains(&(ra, rb)) {
+            return Ok(true);
+        }
+        seen.push((ra, rb));
+        // Los elementos de tipos incomparables simplemente difieren.
+        let mut same = |x: &Value, y: &Value| self.equals_in(x, y, seen).unwrap_or(false);
+        let result = match (self.get(ra), self.get(rb)) {
+            (HeapObject::Array(xs), HeapObject::Array(ys)) => {
+                xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| same(x, y))
+            }
+            (HeapObject::Map(xs), HeapObject::Map(ys)) => {
+                xs.len() == ys.len()
+                    && xs
+                        .iter()
+                        .zip(ys)
+                        .all(|((kx, x), (ky, y))| kx == ky && same(x, y))
+            }
+            _ => false,
+        };
+        seen.pop();
+        Ok(result)
+    }
+
+    /// Valor de verdad; con `truthy` los arreglos y mapas vacíos son falsos.
+    pub fn truth(&self, val: &Value, truthy: bool) -> Result<bool, VmErrorKind> {
+        match val {
+            Value::Array(r) | Value::Map(r) if truthy => Ok(!self.get(*r).is_empty()),
+            val => val.truth(truthy),
+        }
+    }
+}
+
+fn cycle_marker(val: &Value) -> &'static str {
+    match val {
+        Value::Map(_) => "{...}",
+        _ => "[...]",
+    }
+}
@@ -0,0 +1,192 @@
+use super::{Frame, HeapObject, HeapRef, Instruction, Value};
+use std::collections::VecDeque;
+
+/// Variable que una instrucción sobrescribió, con su valor anterior.
+#[derive(Debug, Clone)]
+pub(super) enum VarWrite {
+    Local {
+        name: String,
+        previous: Option<Value>,
+    },
+    Global {
+        name: String,
+        previous: Option<Value>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub(super) enum FrameChange {
+    /// `CALL` creó un marco.
+    Pushed,
+    /// `RET` descartó este marco.
+    Popped(Frame),
+}
+
+/// Efectos de una instrucción, suficientes para deshacerla.
+#[derive(Debug, Clone)]
+pub(super) struct UndoEntry {
+    pub ip: usize,
+    /// Tamaño de la pila sin los valores que la instrucción consume.
+    pub base: usize,
+    /// Valores que la instrucción consume, en el orden en que estaban.
+    pub consumed: Vec<Value>,
+    pub var: Option<VarWrite>,
+    pub frame: Option<FrameChange>,
+    /// Contenido previo del objeto que la instrucción modificó.
+    pub object: Option<(HeapRef, HeapObject)>,
+    /// Objeto creado por la instrucción.
+    pub allocated: Option<HeapRef>,
+}
+
+impl UndoEntry {
+    /// Objetos del heap a los que hace referencia la entrada; deben
+    /// sobrevivir al recolector mientras la entrada exista.
+    fn refs(&self) -> impl Iterator<Item = HeapRef> + '_ {
+        let var = self.var.as_ref().and_then(|var| match var {
+            VarWrite::Local { previous, .. } | VarWrite::Global { previous, .. } => {
+                previous.as_ref()
+            }
+        });
+        let frame = match &self.frame {
+            Some(FrameChange::Popped(frame)) => Some(frame.locals.values()),
+            _ => None,
+        };
+        let object = self.object.iter().flat_map(|(r, object)| {
+            let children: Vec<&Value> = match object {
+                HeapObject::Array(items) => items.iter().collect(),
+                HeapObject::Map(entries) => entries.values().collect(),
+            };
+            std::iter::once(*r).chain(children.into_iter().filter_map(Value::heap_ref))
+        });
+        self.consumed
+            .iter()
+            .chain(var)
+            .chain(frame.into_iter().flatten())
+            .filter_map(Value::heap_ref)
+            .chain(object)
+            .chain(self.allocated)
+    }
+}
+
+/// Registro de los últimos pasos ejecutados, del más antiguo al más
+/// reciente. Al llenarse se descartan los más antiguos.
+#[derive(Debug, Clone, Default)]
+pub(super) struct History {
+    entries: VecDeque<UndoEntry>,
+}
+
+impl History {
+    pub fn push(&mut self, entry: UndoEntry, limit: usize) {
+        if limit == 0 {
+            return;
+        }
+        while self.entries.len() >= limit {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn pop(&mut self) -> Option<UndoEntry> {
+        self.entries.pop_back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn refs(&self) -> impl Iterator<Item = HeapRef> + '_ {
+        self.entries.iter().flat_map(UndoEntry::refs)
+    }
+}
+
+/// Cuántos valores del tope de la pila consume la instrucción.
+pub(super) fn stack_inputs(instr: &Instruction) -> usize {
+    match instr {
+        Instruction::LoadConstFloat(_)
+        | Instruction::LoadConstInt(_)
+        | Instruction::LoadConstBool(_)
+        | Instruction::LoadConstStr(_)
+        | Instruction::LoadVar(_)
+        | Instruction::LoadGlobal(_)
+        | Instruction::Read
+        | Instruction::Jmp(_)
+        | Instruction::Call(_)
+        | Instruction::Ret
+        | Instruction::Dup
+        | Instruction::Over
+        | Instruction::Pick(_)
+        | Instruction::NewMap => 0,
+        Instruction::StoreVar(_)
+        | Instruction::StoreGlobal(_)
+        | Instruction::Print
+        | Instruction::JmpEq(_)
+        | Instruction::JmpNe(_)
+        | Instruction::JmpGe(_)
+        | Instruction::JmpGt(_)
+        | Instruction::JmpLt(_)
+        | Instruction::JmpLe(_)
+        | Instruction::JmpTrue(_)
+        | Instruction::JmpFalse(_)
+        | Instruction::Pop
+        | Instruction::Not
+        | Instruction::Len
+        | Instruction::ToStr
+        | Instruction::ToNum
+        | Instruction::ToFloat
+        | Instruction::Num
+        | Instruction::Den
+        | Instruction::ArrayLen
+        | Instruction::ArrayPop
+        | Instruction::MapKeys => 1,
+        Instruction::Add
+        | Instruction::Sub
+        | Instruction::Mul
+        | Instruction::Div
+        | Instruction::IDiv
+        | Instruction::Pow
+        | Instruction::Mod
+        | Instruction::Swap
+        | Instruction::Eq
+        | Instruction::Ne
+        | Instruction::Lt
+        | Instruction::Le
+        | Instruction::Gt
+        | Instruction::Ge
+        | Instruction::And
+        | Instruction::Or
+        | Instruction::Xor
+        | Instruction::Concat
+        | Instruction::ArrayGet
+        | Instruction::ArrayPush
+        | Instruction::MapGet
+        | Instruction::MapHas
+        | Instruction::MapDel => 2,
+        Instruction::Rot | Instruction::Substr | Instruction::ArraySet | Instruction::MapSet => 3,
+        Instruction::NewArray(n) => *n,
+    }
+}
+
+/// Instrucciones que modifican el arreglo o mapa que está en la base de
+/// sus operandos.
+pub(super) fn mutates_object(instr: &Instruction) -> bool {
+    matches!(
+        instr,
+        Instruction::ArraySet
+            | Instruction::ArrayPush
+            | Instruction::ArrayPop
+            | Instruction::MapSet
+            | Instruction::MapDel
+    )
+}
+
+/// Instrucciones que dejan en el tope un objeto recién creado.
+pub(super) fn allocates(instr: &Instruction) -> bool {
+    matches!(
+        instr,
+        Instruction::NewArray(_) | Instruction::NewMap | Instruction::MapKeys
+    )
+}
@@ -0,0 +1,155 @@
+use vainilla_machine::parse::Parser;
+use vainilla_machine::vm::{OverflowMode, VmConfig, VmErrorKind, VM};
+
+/// Calcula `a op b` y devuelve el resultado como texto.
+fn eval(a: &str, op: &str, b: &str, config: VmConfig) -> Result<String, VmErrorKind> {
+    let source = format!("LOAD_CONST {}\nLOAD_CONST {}\n{}\n", a, b, op);
+    let mut vm = VM::with_config(Parser::new().parse_file(&source).unwrap(), config);
+    vm.run().map_err(|error| error.kind)?;
+    Ok(vm.heap().repr(&vm.stack()[0]))
+}
+
+fn mode(overflow: OverflowMode) -> VmConfig {
+    VmConfig {
+        overflow,
+        ..VmConfig::default()
+    }
+}
+
+const MAX: &str = "9223372036854775807";
+const MIN: &str = "-9223372036854775808";
+const TWO_TO_32: &str = "4294967296";
+
+#[test]
+fn error_mode_reports_overflow() {
+    let config = mode(OverflowMode::Error);
+    assert_eq!(
+        eval(MAX, "ADD", "1", config.clone()),
+        Err(VmErrorKind::IntegerOverflow)
+    );
+    assert_eq!(
+        eval("2", "POW", "64", config.clone()),
+        Err(VmErrorKind::IntegerOverflow)
+    );
+    assert_eq!(eval("3", "POW", "4", config), Ok("81".to_string()));
+}
+
+#[test]
+fn wrap_mode_uses_twos_complement() {
+    let config = mode(OverflowMode::Wrap);
+    assert_eq!(eval(MAX, "ADD", "1", config.clone()), Ok(MIN.to_string()));
+    assert_eq!(eval(MIN, "MUL", "-1", config.clone()), Ok(MIN.to_string()));
+    assert_eq!(eval("2", "POW", "64", config.clone()), Ok("0".to_string()));
+    assert_eq!(
+        eval("3", "POW", TWO_TO_32, config),
+        Ok("2491309678558969857".to_string())
+    );
+}
+
+#[test]
+fn saturate_mode_clamps_to_the_limits() {
+    let config = mode(OverflowMode::Saturate);
+    assert_eq!(eval(MAX, "ADD", "1", config.clone()), Ok(MAX.to_string()));
+    assert_eq!(eval(MIN, "SUB", "1", config.clone()), Ok(MIN.to_string()));
+    assert_eq!(
+        eval("2", "POW", TWO_TO_32, config.clone()),
+        Ok(MAX.to_string())
+    );
+    assert_eq!(eval("-2", "POW", "4294967297", config), Ok(MIN.to_string()));
+}
+
+#[test]
+fn promote_mode_falls_back_to_floats() {
+    let config = mode(OverflowMode::Promote);
+    assert_eq!(
+        eval(MAX, "ADD", "1", config.clone()),
+        Ok("9223372036854776000".to_string())
+    );
+    assert_eq!(
+        eval("2", "POW", "64", config),
+        Ok("18446744073709552000".to_string())
+    );
+}
+
+#[test]
+fn powers_of_zero_and_one_never_overflow() {
+    for overflow in [
+        OverflowMode::Error,
+        OverflowMode::Wrap,
+        OverflowMode::Saturate,
+        OverflowMode::Promote,
+    ] {
+        let config = mode(overflow);
+        assert_eq!(
+            eval("1", "POW", TWO_TO_32, config.clone()),
+            Ok("1".to_string())
+        );
+        assert_eq!(
+            eval("0", "POW", TWO_TO_32, config.clone()),
+            Ok("0".to_string())
+        );
+        assert_eq!(
+            eval("-1", "POW", TWO_TO_32, config.clone()),
+            Ok("1".to_string())
+        );
+        assert_eq!(
+            eval("-1", "POW", "4294967297", config),
+            Ok("-1".to_string())
+        );
+    }
+}
+
+#[test]
+fn bigint_promotes_and_normalizes_back() {
+    let config = VmConfig {
+        bigint: true,
+        ..VmConfig::default()
+    };
+    assert_eq!(
+        eval(MAX, "ADD", "1", config.clone()),
+        Ok("9223372036854775808".to_string())
+    );
+    assert_eq!(
+        eval("2", "POW", "100", config.clone()),
+        Ok("1267650600228229401496703205376".to_string())
+    );
+    let source = format!("LOAD_CONST {}\nLOAD_CONST 1\nADD\nLOAD_CONST 1\nSUB\n", MAX);
+    let mut vm = VM::with_config(Parser::new().parse_file(&source).unwrap(), config);
+    vm.run().unwrap();
+    assert_eq!(vm.heap().repr(&vm.stack()[0]), MAX);
+}
+
+#[test]
+fn rational_powers_of_one_with_huge_negative_exponents() {
+    let config = VmConfig {
+        rational: true,
+        ..VmConfig::default()
+    };
+    assert_eq!(
+        eval("-1", "POW", "-4294967297", config.clone()),
+        Ok("-1".to_string())
+    );
+    assert_eq!(eval("2", "POW", "-3", config), Ok("1/8".to_string()));
+}
+
+#[test]
+fn rational_division_is_exact() {
+    let config = VmConfig {
+        rational: true,
+        ..VmConfig::default()
+    };
+    assert_eq!(eval("1", "DIV", "3", config.clone()), Ok("1/3".to_string()));
+    assert_eq!(eval("6", "DIV", "3", config.clone()), Ok("2".to_string()));
+    let source = "LOAD_CONST 1\nLOAD_CONST 3\nDIV\nLOAD_CONST 3\nMUL\nLOAD_CONST 1\nEQ\n";
+    let mut vm = VM::with_config(Parser::new().parse_file(source).unwrap(), config.clone());
+    vm.run().unwrap();
+    assert_eq!(vm.heap().repr(&vm.stack()[0]), "true");
+
+    assert_eq!(
+        eval("1", "DIV", "0", config.clone()),
+        Err(VmErrorKind::DivisionByZero)
+    );
+    let source = "LOAD_CONST 1\nLOAD_CONST 2\nDIV\nLOAD_CONST 0\nDIV\n";
+    let mut vm = VM::with_config(Parser::new().parse_file(source).unwrap(), config);
+    assert_eq!(vm.run().unwrap_err().kind, VmErrorKind::DivisionByZero);
+}
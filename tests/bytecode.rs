@@ -0,0 +1,135 @@
+use std::fs;
+use vainilla_machine::bytecode::{self, BytecodeError};
+use vainilla_machine::parse::Parser;
+use vainilla_machine::program::Program;
+use vainilla_machine::vm::Instruction;
+
+fn examples() -> Vec<(String, Program)> {
+    let dir = format!("{}/examples", env!("CARGO_MANIFEST_DIR"));
+    let mut examples: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "vm"))
+        .collect();
+    examples.sort();
+    examples
+        .into_iter()
+        .map(|path| {
+            let name = path.to_string_lossy().into_owned();
+            let contents = fs::read_to_string(&path).unwrap();
+            let program = Parser::with_file(&name).parse_file(&contents).unwrap();
+            (name, program)
+        })
+        .collect()
+}
+
+#[test]
+fn examples_survive_encoding_and_disassembly() {
+    for (name, program) in examples() {
+        let decoded = bytecode::decode(&bytecode::encode(&program, true).unwrap()).unwrap();
+        assert_eq!(decoded.instructions, program.instructions, "{}", name);
+        assert_eq!(decoded.labels, program.labels, "{}", name);
+        assert_eq!(decoded.source_map, program.source_map, "{}", name);
+
+        let text = bytecode::disassemble(&decoded);
+        let reassembled = Parser::new().parse_file(&text).unwrap();
+        assert_eq!(reassembled.instructions, program.instructions, "{}", name);
+    }
+}
+
+#[test]
+fn stripped_bytecode_keeps_the_instructions() {
+    for (name, program) in examples() {
+        let decoded = bytecode::decode(&bytecode::encode(&program, false).unwrap()).unwrap();
+        assert_eq!(decoded.instructions, program.instructions, "{}", name);
+        assert!(decoded.labels.is_empty(), "{}", name);
+    }
+}
+
+#[test]
+fn operands_that_do_not_fit_are_rejected() {
+    let big = u32::MAX as usize + 1;
+    for instr in [
+        Instruction::Pick(big),
+        Instruction::NewArray(big),
+        Instruction::Jmp(big),
+    ] {
+        let program = Program {
+            instructions: vec![instr],
+            ..Program::default()
+        };
+        assert_eq!(
+            bytecode::encode(&program, false),
+            Err(BytecodeError::TooLarge(big))
+        );
+    }
+}
+
+#[test]
+fn targets_outside_the_program_are_rejected() {
+    let program = Program {
+        instructions: vec![Instruction::Jmp(1), Instruction::Call(3)],
+        ..Program::default()
+    };
+    let bytes = bytecode::encode(&program, false).unwrap();
+    assert_eq!(
+        bytecode::decode(&bytes).err(),
+        Some(BytecodeError::InvalidTarget(3))
+    );
+
+    let mut program = Parser::new().parse_file("LOAD_CONST 1\nfin:\n").unwrap();
+    program.labels.insert("lejos".to_string(), 7);
+    let bytes = bytecode::encode(&program, true).unwrap();
+    assert_eq!(
+        bytecode::decode(&bytes).err(),
+        Some(BytecodeError::InvalidTarget(7))
+    );
+}
+
+#[test]
+fn source_map_must_cover_every_instruction() {
+    let mut program = Parser::new().parse_file("LOAD_CONST 1\nPRINT\n").unwrap();
+    program.source_map.push(3, "POP");
+    let bytes = bytecode::encode(&program, true).unwrap();
+    assert_eq!(
+        bytecode::decode(&bytes).err(),
+        Some(BytecodeError::SourceMapMismatch {
+            entries: 3,
+            instructions: 2
+        })
+    );
+}
+
+#[test]
+fn load_reads_source_and_bytecode() {
+    let dir = std::env::temp_dir();
+    let name = |ext: &str| {
+        dir.join(format!("vainilla-load-{}.{}", std::process::id(), ext))
+            .to_string_lossy()
+            .into_owned()
+    };
+    let source = name("vm");
+    fs::write(&source, "LOAD_CONST 1\nPRINT\n").unwrap();
+    let program = bytecode::load(&source).unwrap();
+    assert_eq!(program.len(), 2);
+
+    let binary = name("vmb");
+    fs::write(&binary, bytecode::encode(&program, true).unwrap()).unwrap();
+    assert_eq!(
+        bytecode::load(&binary).unwrap().instructions,
+        program.instructions
+    );
+
+    let text = name("txt");
+    fs::write(&text, "LOAD_CONST 1\n").unwrap();
+    assert!(bytecode::load(&text).unwrap_err().contains("extensión .vm"));
+
+    fs::write(&source, "NOPE\nLOAD_CONST\n").unwrap();
+    let error = bytecode::load(&source).unwrap_err();
+    assert!(error.contains("NOPE"), "{}", error);
+    assert!(
+        error.ends_with("2 error(es) al ensamblar el programa"),
+        "{}",
+        error
+    );
+}
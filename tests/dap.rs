@@ -0,0 +1,341 @@
+#![cfg(feature = "serde")]
+
+use serde_json::{json, Value};
+use std::fs;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use vainilla_machine::protocol::{read_message, write_message};
+
+/// Cliente DAP mínimo que habla con `vainilla-machine dap` por tuberías.
+struct Client {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    seq: u64,
+    /// Eventos recibidos que todavía no se han consultado.
+    events: Vec<Value>,
+}
+
+impl Client {
+    fn start() -> Client {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_vainilla-machine"))
+            .arg("dap")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Client {
+            child,
+            stdin,
+            stdout,
+            seq: 0,
+            events: Vec::new(),
+        }
+    }
+
+    fn receive(&mut self) -> Value {
+        read_message(&mut self.stdout)
+            .unwrap()
+            .expect("el adaptador cerró la conexión")
+    }
+
+    fn request(&mut self, command: &str, arguments: Value) -> Value {
+        self.seq += 1;
+        let request = json!({
+            "seq": self.seq,
+            "type": "request",
+            "command": command,
+            "arguments": arguments,
+        });
+        write_message(&mut self.stdin, &request).unwrap();
+        loop {
+            let message = self.receive();
+            if message["type"] == "event" {
+                self.events.push(message);
+                continue;
+            }
+            assert_eq!(message["request_seq"], self.seq);
+            assert_eq!(message["command"], command);
+            return message;
+        }
+    }
+
+    fn event(&mut self, name: &str) -> Value {
+        if let Some(ix) = self.events.iter().position(|event| event["event"] == name) {
+            return self.events.remove(ix);
+        }
+        loop {
+            let message = self.receive();
+            if message["event"] == name {
+                return message;
+            }
+            self.events.push(message);
+        }
+    }
+
+    /// `initialize` y `launch`, con la entrada de `READ` si se da.
+    fn launch(&mut self, program: &str, input: Option<&str>, stop_on_entry: bool) -> Value {
+        let response = self.request("initialize", json!({ "adapterID": "vainilla" }));
+        assert_eq!(response["success"], true);
+        let mut args = json!({ "program": program, "stopOnEntry": stop_on_entry });
+        if let Some(input) = input {
+            args["input"] = json!(temp_file("entrada", input));
+        }
+        let response = self.request("launch", args);
+        if response["success"] == true {
+            self.event("initialized");
+        }
+        response
+    }
+
+    fn set_breakpoints(&mut self, program: &str, breakpoints: Value) -> Value {
+        let response = self.request(
+            "setBreakpoints",
+            json!({ "source": { "path": program }, "breakpoints": breakpoints }),
+        );
+        assert_eq!(response["success"], true);
+        response["body"]["breakpoints"].clone()
+    }
+
+    fn frames(&mut self) -> Vec<Value> {
+        let response = self.request("stackTrace", json!({ "threadId": 1 }));
+        response["body"]["stackFrames"].as_array().unwrap().clone()
+    }
+
+    /// Variables de un ámbito del marco más interno, como pares nombre/valor.
+    fn scope(&mut self, name: &str) -> Vec<(String, String)> {
+        let scopes = self.request("scopes", json!({ "frameId": 0 }));
+        let reference = scopes["body"]["scopes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|scope| scope["name"] == name)
+            .unwrap()["variablesReference"]
+            .clone();
+        self.variables(reference)
+    }
+
+    fn variables(&mut self, reference: Value) -> Vec<(String, String)> {
+        let response = self.request("variables", json!({ "variablesReference": reference }));
+        response["body"]["variables"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|var| {
+                (
+                    var["name"].as_str().unwrap().to_string(),
+                    var["value"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    fn disconnect(mut self) {
+        self.request("disconnect", json!({}));
+        assert!(self.child.wait().unwrap().success());
+    }
+}
+
+fn example(name: &str) -> String {
+    format!("{}/examples/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+fn temp_file(name: &str, contents: &str) -> String {
+    let path: PathBuf = std::env::temp_dir().join(format!(
+        "vainilla-dap-{}-{:?}-{}",
+        std::process::id(),
+        std::thread::current().id(),
+        name
+    ));
+    fs::write(&path, contents).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+fn pair(name: &str, value: &str) -> (String, String) {
+    (name.to_string(), value.to_string())
+}
+
+#[test]
+fn stops_at_line_breakpoints_and_shows_variables() {
+    let program = example("factorial.vm");
+    let mut client = Client::start();
+    client.launch(&program, Some("5\n"), false);
+    let breakpoints = client.set_breakpoints(&program, json!([{ "line": 12 }, { "line": 20 }]));
+    assert_eq!(breakpoints[0]["verified"], true);
+    assert_eq!(breakpoints[0]["line"], 12);
+    assert_eq!(breakpoints[1]["verified"], false);
+    client.request("configurationDone", json!({}));
+
+    let stopped = client.event("stopped");
+    assert_eq!(stopped["body"]["reason"], "breakpoint");
+    assert_eq!(
+        stopped["body"]["hitBreakpointIds"],
+        json!([breakpoints[0]["id"]])
+    );
+    let frames = client.frames();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0]["line"], 12);
+    assert_eq!(
+        client.scope("Globales"),
+        vec![pair("fact", "5"), pair("x", "5")]
+    );
+
+    client.request("continue", json!({}));
+    client.event("stopped");
+    assert_eq!(
+        client.scope("Globales"),
+        vec![pair("fact", "20"), pair("x", "4")]
+    );
+
+    client.set_breakpoints(&program, json!([]));
+    client.request("continue", json!({}));
+    assert_eq!(client.event("output")["body"]["output"], "120\n");
+    client.event("terminated");
+    client.disconnect();
+}
+
+#[test]
+fn conditional_breakpoints_and_evaluate() {
+    let program = example("factorial.vm");
+    let mut client = Client::start();
+    client.launch(&program, Some("5\n"), false);
+    client.set_breakpoints(&program, json!([{ "line": 8, "condition": "x == 2" }]));
+    client.request("configurationDone", json!({}));
+    client.event("stopped");
+    let response = client.request("evaluate", json!({ "expression": "fact", "frameId": 0 }));
+    assert_eq!(response["body"]["result"], "60");
+    let response = client.request("evaluate", json!({ "expression": "nada" }));
+    assert_eq!(response["success"], false);
+    client.disconnect();
+}
+
+#[test]
+fn steps_into_over_and_out_of_subroutines() {
+    let program = example("subrutina.vm");
+    let mut client = Client::start();
+    client.launch(&program, Some("7\n"), true);
+    client.request("configurationDone", json!({}));
+    assert_eq!(client.event("stopped")["body"]["reason"], "entry");
+
+    client.request("next", json!({ "threadId": 1 }));
+    client.event("stopped");
+    client.request("next", json!({ "threadId": 1 }));
+    client.event("stopped");
+    assert_eq!(client.frames()[0]["line"], 4);
+
+    client.request("stepIn", json!({ "threadId": 1 }));
+    client.event("stopped");
+    let frames = client.frames();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0]["name"], "cuadrado");
+    assert_eq!(frames[0]["line"], 11);
+    assert_eq!(frames[1]["line"], 4);
+
+    client.request("stepOut", json!({ "threadId": 1 }));
+    assert_eq!(client.event("stopped")["body"]["reason"], "step");
+    let frames = client.frames();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0]["line"], 5);
+    assert_eq!(client.scope("Globales"), vec![pair("x", "49")]);
+
+    client.request("stepBack", json!({ "threadId": 1 }));
+    client.event("stopped");
+    assert_eq!(client.frames().len(), 2);
+
+    client.request("stepBack", json!({ "threadId": 1 }));
+    client.event("stopped");
+    assert_eq!(client.scope("Globales"), vec![pair("x", "7")]);
+    client.disconnect();
+}
+
+#[test]
+fn next_runs_calls_to_completion() {
+    let program = example("subrutina.vm");
+    let mut client = Client::start();
+    client.launch(&program, Some("7\n"), false);
+    client.set_breakpoints(&program, json!([{ "line": 4 }]));
+    client.request("configurationDone", json!({}));
+    client.event("stopped");
+    client.request("next", json!({ "threadId": 1 }));
+    client.event("stopped");
+    let frames = client.frames();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(frames[0]["line"], 5);
+    client.disconnect();
+}
+
+#[test]
+fn pauses_a_running_program() {
+    let program = temp_file("ciclo.vm", "ciclo:\nLOAD_CONST 1\nPOP\nJMP ciclo\n");
+    let mut client = Client::start();
+    client.launch(&program, None, false);
+    client.request("configurationDone", json!({}));
+    client.request("pause", json!({ "threadId": 1 }));
+    assert_eq!(client.event("stopped")["body"]["reason"], "pause");
+    assert!(client.frames()[0]["line"].as_u64().unwrap() >= 2);
+    client.disconnect();
+}
+
+#[test]
+fn expands_arrays_and_maps() {
+    let program = example("registro.vm");
+    let mut client = Client::start();
+    client.launch(&program, None, false);
+    client.request("configurationDone", json!({}));
+    client.event("terminated");
+    let globals = client.request("variables", json!({ "variablesReference": 1 }));
+    let persona = globals["body"]["variables"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|var| var["name"] == "persona")
+        .unwrap()
+        .clone();
+    assert_eq!(persona["type"], "map");
+    let entries = client.variables(persona["variablesReference"].clone());
+    assert!(!entries.is_empty());
+    client.disconnect();
+}
+
+#[test]
+fn reports_runtime_errors_and_bad_programs() {
+    let program = temp_file("division.vm", "LOAD_CONST 1\nLOAD_CONST 0\nDIV\n");
+    let mut client = Client::start();
+    client.launch(&program, None, false);
+    client.request("configurationDone", json!({}));
+    let stopped = client.event("stopped");
+    assert_eq!(stopped["body"]["reason"], "exception");
+    assert_eq!(stopped["body"]["text"], "división entre cero");
+    client.disconnect();
+
+    let program = temp_file("invalido.vm", "LOAD_CONST 1\nNOPE\n");
+    let mut client = Client::start();
+    let response = client.launch(&program, None, false);
+    assert_eq!(response["success"], false);
+    assert!(response["message"].as_str().unwrap().contains("NOPE"));
+    client.disconnect();
+}
+
+#[test]
+fn stops_at_a_breakpoint_on_the_first_instruction() {
+    let program = temp_file(
+        "primera.vm",
+        "; una instrucción por línea\nLOAD_CONST 1\nPRINT\n",
+    );
+    let mut client = Client::start();
+    client.launch(&program, None, false);
+    let breakpoints = client.set_breakpoints(&program, json!([{ "line": 2 }]));
+    assert_eq!(breakpoints[0]["verified"], true);
+    client.request("configurationDone", json!({}));
+
+    let stopped = client.event("stopped");
+    assert_eq!(stopped["body"]["reason"], "breakpoint");
+    assert_eq!(client.frames()[0]["line"], 2);
+    client.request("continue", json!({}));
+    assert_eq!(client.event("output")["body"]["output"], "1\n");
+    client.event("terminated");
+    client.disconnect();
+}
@@ -0,0 +1,128 @@
+use vainilla_machine::debugger::{parse_literal, DebugError, Debugger, Stop, WatchKind};
+use vainilla_machine::parse::Parser;
+use vainilla_machine::vm::{VmConfig, VM};
+
+// Las instrucciones van de la #0 a la #9; `ciclo` es la #2 y está en la
+// línea 4.
+const PROGRAM: &str = "\
+LOAD_CONST 0
+STORE_VAR i
+ciclo:
+LOAD_VAR i
+LOAD_CONST 1
+ADD
+STORE_VAR i
+LOAD_VAR i
+LOAD_CONST 3
+SUB
+JMPLT ciclo
+";
+
+fn vm() -> VM {
+    let config = VmConfig {
+        history_limit: 100,
+        ..VmConfig::default()
+    };
+    VM::with_config(Parser::new().parse_file(PROGRAM).unwrap(), config)
+}
+
+fn var(vm: &VM, name: &str) -> String {
+    vm.heap().repr(vm.lookup_var(name).unwrap())
+}
+
+#[test]
+fn breakpoints_stop_before_their_instruction() {
+    let mut vm = vm();
+    let mut debugger = Debugger::new();
+    let id = debugger.add_breakpoint(vm.program(), "ciclo").unwrap().id;
+
+    for i in 0..3 {
+        assert_eq!(debugger.resume(&mut vm), Ok(Stop::Breakpoint(id)));
+        assert_eq!(vm.ip(), 2);
+        assert_eq!(var(&vm, "i"), i.to_string());
+    }
+    assert_eq!(debugger.resume(&mut vm), Ok(Stop::Halted));
+}
+
+#[test]
+fn breakpoint_locations_resolve_to_addresses() {
+    let vm = vm();
+    let mut debugger = Debugger::new();
+    for (spec, address) in [("ciclo", 2), ("#6", 6), ("4", 2), ("6", 4)] {
+        let bp = debugger.add_breakpoint(vm.program(), spec).unwrap();
+        assert_eq!(bp.address, address, "{}", spec);
+    }
+    assert!(debugger.add_breakpoint(vm.program(), "nada").is_err());
+    assert!(debugger.add_breakpoint(vm.program(), "#10").is_err());
+    assert!(debugger.add_breakpoint(vm.program(), "40").is_err());
+}
+
+#[test]
+fn conditional_breakpoints_wait_for_the_condition() {
+    let mut vm = vm();
+    let mut debugger = Debugger::new();
+    let id = debugger
+        .add_breakpoint(vm.program(), "#6 if i == 2")
+        .unwrap()
+        .id;
+    assert_eq!(debugger.resume(&mut vm), Ok(Stop::Breakpoint(id)));
+    assert_eq!(vm.ip(), 6);
+    assert_eq!(var(&vm, "i"), "2");
+    assert_eq!(debugger.resume(&mut vm), Ok(Stop::Halted));
+}
+
+#[test]
+fn watchpoints_stop_after_the_write() {
+    let mut vm = vm();
+    let mut debugger = Debugger::new();
+    let id = debugger.add_watchpoint(&vm, "i", WatchKind::Change).id;
+
+    assert_eq!(
+        debugger.resume(&mut vm),
+        Ok(Stop::Watchpoint {
+            id,
+            old: None,
+            new: Some("0".to_string()),
+        })
+    );
+    assert_eq!(vm.ip(), 2);
+    assert_eq!(
+        debugger.resume(&mut vm),
+        Ok(Stop::Watchpoint {
+            id,
+            old: Some("0".to_string()),
+            new: Some("1".to_string()),
+        })
+    );
+    assert_eq!(vm.ip(), 6);
+}
+
+#[test]
+fn reverse_returns_to_the_previous_breakpoint() {
+    let mut vm = vm();
+    let mut debugger = Debugger::new();
+    let id = debugger.add_breakpoint(vm.program(), "ciclo").unwrap().id;
+    debugger.resume(&mut vm).unwrap();
+    debugger.resume(&mut vm).unwrap();
+    assert_eq!(var(&vm, "i"), "1");
+
+    assert_eq!(debugger.reverse(&mut vm), Stop::Breakpoint(id));
+    assert_eq!(vm.ip(), 2);
+    assert_eq!(var(&vm, "i"), "0");
+    assert_eq!(debugger.reverse(&mut vm), Stop::HistoryStart);
+    assert_eq!(vm.ip(), 0);
+}
+
+#[test]
+fn literals_outside_i64_need_bigint() {
+    let big = "9223372036854775808";
+    assert_eq!(
+        parse_literal(big, false).err(),
+        Some(DebugError::OutOfRange(big.to_string()))
+    );
+    let val = parse_literal(big, true).unwrap();
+    assert_eq!(val.repr(), big);
+    assert_eq!(parse_literal("-12", false).unwrap().repr(), "-12");
+    assert_eq!(parse_literal("2.5", false).unwrap().repr(), "2.5");
+    assert!(parse_literal("x", false).is_err());
+}
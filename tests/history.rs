@@ -0,0 +1,125 @@
+use vainilla_machine::parse::Parser;
+use vainilla_machine::vm::{StepOutcome, VmConfig, VM};
+
+fn vm(source: &str, history_limit: usize) -> VM {
+    let config = VmConfig {
+        history_limit,
+        ..VmConfig::default()
+    };
+    let mut vm = VM::with_config(Parser::new().parse_file(source).unwrap(), config);
+    vm.set_output(std::io::sink());
+    vm
+}
+
+/// Lo que el programa puede observar: ip, pila, variables y marcos, con
+/// los arreglos y mapas ya expandidos.
+fn observe(vm: &VM) -> String {
+    let repr = |val| vm.heap().repr(val);
+    let stack: Vec<String> = vm.stack().iter().map(repr).collect();
+    let mut globals: Vec<String> = vm
+        .globals()
+        .iter()
+        .map(|(name, val)| format!("{}={}", name, repr(val)))
+        .collect();
+    globals.sort();
+    let frames: Vec<String> = vm
+        .call_stack()
+        .iter()
+        .map(|frame| {
+            let mut locals: Vec<String> = frame
+                .locals
+                .iter()
+                .map(|(name, val)| format!("{}={}", name, repr(val)))
+                .collect();
+            locals.sort();
+            format!("{}:{}", vm.function_name(Some(frame)), locals.join(","))
+        })
+        .collect();
+    format!(
+        "ip={} pila={:?} globales={:?} marcos={:?}",
+        vm.ip(),
+        stack,
+        globals,
+        frames
+    )
+}
+
+const PROGRAM: &str = "\
+LOAD_CONST 1
+LOAD_CONST 2
+NEW_ARRAY 2
+STORE_VAR lista
+LOAD_VAR lista
+LOAD_CONST 0
+LOAD_CONST 9
+ARRAY_SET
+POP
+NEW_MAP
+LOAD_CONST \"k\"
+LOAD_CONST 5
+MAP_SET
+STORE_VAR mapa
+CALL sub
+LOAD_VAR x
+PRINT
+JMP fin
+sub:
+LOAD_CONST 7
+STORE_VAR x
+LOAD_CONST 8
+STORE_GLOBAL x
+RET
+fin:
+";
+
+#[test]
+fn stepping_back_restores_every_previous_state() {
+    let mut vm = vm(PROGRAM, 100);
+    let mut states = vec![observe(&vm)];
+    while vm.step().unwrap() == StepOutcome::Continue {
+        states.push(observe(&vm));
+    }
+    let last = observe(&vm);
+    assert!(last.contains("lista=[9, 2]"), "{}", last);
+    assert!(last.contains("x=8"), "{}", last);
+
+    while let Some(expected) = states.pop() {
+        assert!(vm.step_back());
+        assert_eq!(observe(&vm), expected);
+    }
+    assert!(!vm.step_back());
+    assert_eq!(vm.history_len(), 0);
+}
+
+#[test]
+fn history_is_bounded_by_the_limit() {
+    let mut vm = vm(PROGRAM, 3);
+    vm.run().unwrap();
+    assert_eq!(vm.history_len(), 3);
+    let undone = (0..10).take_while(|_| vm.step_back()).count();
+    assert_eq!(undone, 3);
+
+    let mut vm = self::vm(PROGRAM, 0);
+    vm.run().unwrap();
+    assert!(!vm.step_back());
+}
+
+#[test]
+fn a_failed_step_can_be_undone() {
+    let mut vm = vm("LOAD_CONST \"a\"\nLOAD_CONST 1\nADD\n", 10);
+    vm.run_steps(2).unwrap();
+    let before = observe(&vm);
+    vm.step().unwrap_err();
+    assert!(vm.step_back());
+    assert_eq!(observe(&vm), before);
+}
+
+#[test]
+fn undone_allocations_are_released() {
+    let mut vm = vm("NEW_ARRAY 0\nNEW_MAP\n", 10);
+    vm.run().unwrap();
+    assert_eq!(vm.heap().len(), 2);
+    assert!(vm.step_back());
+    assert!(vm.step_back());
+    assert_eq!(vm.heap().len(), 0);
+}
@@ -0,0 +1,301 @@
+#![cfg(feature = "serde")]
+
+use serde_json::{json, Value};
+use std::io::{BufReader, Cursor, ErrorKind, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use vainilla_machine::protocol::{read_message, write_message, MAX_MESSAGE_LEN};
+
+const URI: &str = "file:///tmp/ciclo.vm";
+
+const PROGRAM: &str = "\
+ciclo:
+LOAD_VAR i
+LOAD_CONST 1
+ADD
+STORE_VAR i
+JMP ciclo
+";
+
+/// Cliente LSP mínimo que habla con `vainilla-machine lsp` por tuberías.
+struct Client {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    id: u64,
+    /// Notificaciones recibidas que todavía no se han consultado.
+    notifications: Vec<Value>,
+}
+
+impl Client {
+    fn start() -> Client {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_vainilla-machine"))
+            .arg("lsp")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut client = Client {
+            child,
+            stdin,
+            stdout,
+            id: 0,
+            notifications: Vec::new(),
+        };
+        client.request("initialize", json!({ "capabilities": {} }));
+        client.notify("initialized", json!({}));
+        client
+    }
+
+    fn receive(&mut self) -> Value {
+        read_message(&mut self.stdout)
+            .unwrap()
+            .expect("el servidor cerró la conexión")
+    }
+
+    /// Respuesta a la siguiente petición o al mensaje mal formado.
+    fn response(&mut self) -> Value {
+        loop {
+            let message = self.receive();
+            if message.get("method").is_some() {
+                self.notifications.push(message);
+                continue;
+            }
+            return message;
+        }
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> Value {
+        self.id += 1;
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": self.id,
+            "method": method,
+            "params": params,
+        });
+        write_message(&mut self.stdin, &request).unwrap();
+        let response = self.response();
+        assert_eq!(response["id"], self.id);
+        response
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        let message = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        write_message(&mut self.stdin, &message).unwrap();
+    }
+
+    fn notification(&mut self, method: &str) -> Value {
+        if let Some(ix) = self
+            .notifications
+            .iter()
+            .position(|n| n["method"] == method)
+        {
+            return self.notifications.remove(ix);
+        }
+        loop {
+            let message = self.receive();
+            if message["method"] == method {
+                return message;
+            }
+            self.notifications.push(message);
+        }
+    }
+
+    fn open(&mut self, text: &str) -> Value {
+        self.notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": {
+                    "uri": URI,
+                    "languageId": "vainilla",
+                    "version": 1,
+                    "text": text,
+                }
+            }),
+        );
+        self.notification("textDocument/publishDiagnostics")["params"].take()
+    }
+
+    fn at(&mut self, method: &str, line: u64, character: u64) -> Value {
+        let params = json!({
+            "textDocument": { "uri": URI },
+            "position": { "line": line, "character": character },
+        });
+        self.request(method, params)["result"].take()
+    }
+
+    /// `shutdown` y `exit`; el servidor debe terminar sin error.
+    fn finish(mut self) {
+        self.request("shutdown", Value::Null);
+        self.notify("exit", Value::Null);
+        assert!(self.child.wait().unwrap().success());
+    }
+}
+
+#[test]
+fn initialize_announces_the_capabilities() {
+    let mut client = Client::start();
+    let response = client.request("initialize", json!({ "capabilities": {} }));
+    let capabilities = &response["result"]["capabilities"];
+    assert_eq!(capabilities["textDocumentSync"], 1);
+    assert_eq!(capabilities["hoverProvider"], true);
+    assert!(capabilities["completionProvider"].is_object());
+    assert_eq!(response["result"]["serverInfo"]["name"], "vainilla-machine");
+
+    let response = client.request("workspace/symbol", json!({}));
+    assert_eq!(response["error"]["code"], -32601);
+    client.finish();
+}
+
+#[test]
+fn diagnostics_follow_the_document() {
+    let mut client = Client::start();
+    let params = client.open("LOAD_CONST 1\nNOPE\n");
+    assert_eq!(params["uri"], URI);
+    let diagnostics = params["diagnostics"].as_array().unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0]["severity"], 1);
+    assert_eq!(
+        diagnostics[0]["range"],
+        json!({
+            "start": { "line": 1, "character": 0 },
+            "end": { "line": 1, "character": 4 },
+        })
+    );
+    assert!(diagnostics[0]["message"].as_str().unwrap().contains("NOPE"));
+
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": URI, "version": 2 },
+            "contentChanges": [{ "text": PROGRAM }],
+        }),
+    );
+    let params = client.notification("textDocument/publishDiagnostics");
+    assert_eq!(params["params"]["diagnostics"], json!([]));
+    client.finish();
+}
+
+#[test]
+fn hover_describes_instructions_and_labels() {
+    let mut client = Client::start();
+    client.open(PROGRAM);
+
+    let hover = client.at("textDocument/hover", 3, 1);
+    let markdown = hover["contents"]["value"].as_str().unwrap();
+    assert!(markdown.starts_with("```\nADD"), "{}", markdown);
+    assert_eq!(
+        hover["range"],
+        json!({
+            "start": { "line": 3, "character": 0 },
+            "end": { "line": 3, "character": 3 },
+        })
+    );
+
+    let hover = client.at("textDocument/hover", 5, 5);
+    assert_eq!(
+        hover["contents"]["value"],
+        "Etiqueta `ciclo`, definida en la línea 1"
+    );
+    let hover = client.at("textDocument/hover", 1, 10);
+    assert_eq!(hover["contents"]["value"], "Variable `i`");
+    client.finish();
+}
+
+#[test]
+fn completion_depends_on_the_position() {
+    let mut client = Client::start();
+    client.open(&format!("{}JMP \nLOAD_VAR \n", PROGRAM));
+
+    let items = client.at("textDocument/completion", 0, 0);
+    let labels: Vec<&str> = items
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["label"].as_str().unwrap())
+        .collect();
+    assert!(labels.contains(&"LOAD_CONST"));
+    assert!(labels.contains(&"MAP_KEYS"));
+
+    let items = client.at("textDocument/completion", 6, 4);
+    assert_eq!(items, json!([{ "label": "ciclo", "kind": 18 }]));
+    let items = client.at("textDocument/completion", 7, 9);
+    assert_eq!(items, json!([{ "label": "i", "kind": 6 }]));
+    client.finish();
+}
+
+#[test]
+fn bad_input_does_not_stop_the_server() {
+    let mut client = Client::start();
+
+    // Cambio sin texto para un documento que nunca se abrió.
+    client.notify(
+        "textDocument/didChange",
+        json!({
+            "textDocument": { "uri": "file:///tmp/otro.vm", "version": 2 },
+            "contentChanges": [],
+        }),
+    );
+    client
+        .stdin
+        .write_all(b"Content-Length: 6\r\n\r\n{nope}")
+        .unwrap();
+    let response = client.response();
+    assert_eq!(response["id"], Value::Null);
+    assert_eq!(response["error"]["code"], -32700);
+
+    let params = client.open(PROGRAM);
+    assert_eq!(params["uri"], URI);
+    assert!(client.notifications.is_empty());
+    client.finish();
+}
+
+#[test]
+fn oversized_messages_are_rejected_without_allocating() {
+    let mut input = Cursor::new("Content-Length: 99999999999999999\r\n\r\n{}");
+    let error = read_message(&mut input).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+    let header = format!("Content-Length: {}\r\n\r\n", MAX_MESSAGE_LEN + 1);
+    let error = read_message(&mut Cursor::new(header)).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+    let error = read_message(&mut Cursor::new("Content-Length: 10\r\n\r\n{}")).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+
+    let mut client = Client::start();
+    client
+        .stdin
+        .write_all(b"Content-Length: 99999999999999999\r\n\r\n")
+        .unwrap();
+    let response = client.response();
+    assert_eq!(response["error"]["code"], -32700);
+    client.open(PROGRAM);
+    client.finish();
+}
+
+#[test]
+fn positions_outside_the_document_are_invalid_params() {
+    let mut client = Client::start();
+    client.open(PROGRAM);
+    for (method, line, character) in [
+        ("textDocument/hover", u64::MAX, 0),
+        ("textDocument/completion", 7, 0),
+        ("textDocument/definition", 1 << 40, u64::MAX),
+    ] {
+        let params = json!({
+            "textDocument": { "uri": URI },
+            "position": { "line": line, "character": character },
+        });
+        let response = client.request(method, params);
+        assert_eq!(response["error"]["code"], -32602, "{}", method);
+    }
+
+    // Una columna demasiado grande se recorta al final de la línea.
+    let items = client.at("textDocument/completion", 6, u64::MAX);
+    assert!(items.as_array().unwrap().len() > 10);
+    let hover = client.at("textDocument/hover", 3, 1);
+    assert!(hover["contents"]["value"].as_str().unwrap().contains("ADD"));
+    client.finish();
+}
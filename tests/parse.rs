@@ -0,0 +1,86 @@
+use vainilla_machine::parse::{ParseError, ParseErrorKind, Parser};
+use vainilla_machine::vm::Instruction;
+
+fn errors(source: &str) -> Vec<ParseError> {
+    Parser::with_file("prueba.vm")
+        .parse_file(source)
+        .expect_err("el programa debía tener errores")
+}
+
+#[test]
+fn assembles_labels_and_operands() {
+    let program = Parser::new()
+        .parse_file("inicio:\nLOAD_CONST 5 ; comentario\nJMP inicio\n")
+        .unwrap();
+    assert_eq!(
+        program.instructions,
+        vec![Instruction::LoadConstInt(5), Instruction::Jmp(0)]
+    );
+    assert_eq!(program.labels["inicio"], 0);
+    assert_eq!(program.line_of(1), Some(3));
+}
+
+#[test]
+fn rejects_instructions_after_a_label() {
+    let errors = errors("loop: LOAD_CONST 5\nJMP loop\n");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].kind, ParseErrorKind::TextAfterLabel);
+    assert_eq!(errors[0].line, 1);
+    assert_eq!(errors[0].columns, 7..17);
+    assert_eq!(errors[0].token, "LOAD_CONST");
+}
+
+#[test]
+fn rejects_extra_operands() {
+    let errors = errors("LOAD_CONST 1\nADD 5\nLOAD_VAR x y\n");
+    let found: Vec<_> = errors
+        .iter()
+        .map(|error| (error.kind.clone(), error.line, error.token.as_str()))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            (ParseErrorKind::ExtraOperand, 2, "5"),
+            (ParseErrorKind::ExtraOperand, 3, "y"),
+        ]
+    );
+}
+
+#[test]
+fn reports_every_error_with_its_position() {
+    let errors = errors("FOO\nLOAD_CONST\nJMP nada\nLOAD_CONST 1x\nx:\nx:\nLOAD_CONST \"abc\n");
+    let found: Vec<_> = errors
+        .iter()
+        .map(|error| (error.kind.clone(), error.line, error.columns.clone()))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            (ParseErrorKind::UnknownInstruction, 1, 1..4),
+            (
+                ParseErrorKind::MissingOperand {
+                    expected: "un valor constante"
+                },
+                2,
+                1..11
+            ),
+            (ParseErrorKind::UnknownLabel, 3, 5..9),
+            (ParseErrorKind::InvalidNumber, 4, 12..14),
+            (ParseErrorKind::DuplicateLabel { first_line: 5 }, 6, 1..2),
+            (ParseErrorKind::UnterminatedString, 7, 12..16),
+        ]
+    );
+    assert_eq!(
+        errors[0].to_string(),
+        "prueba.vm:1:1: instrucción desconocida: FOO"
+    );
+}
+
+#[test]
+fn renders_a_caret_under_the_token() {
+    let errors = errors("LOAD_CONST 1\nADD 5\n");
+    assert_eq!(
+        errors[0].render(),
+        "error: operando de más: 5\n --> prueba.vm:2:5\n  |\n2 | ADD 5\n  |     ^\n"
+    );
+}
@@ -0,0 +1,101 @@
+use std::fs;
+use std::io::Cursor;
+use vainilla_machine::parse::Parser;
+use vainilla_machine::profile::{Block, Profiler};
+use vainilla_machine::program::Program;
+use vainilla_machine::vm::{StepOutcome, VmConfig, VM};
+
+const PROGRAM: &str = "\
+CALL a
+JMP fin
+a:
+CALL b
+CALL b
+RET
+b:
+LOAD_CONST 1
+POP
+RET
+fin:
+";
+
+/// Ejecuta la VM hasta el final llamando a `record` antes de cada paso.
+fn profile(mut vm: VM) -> (Program, Profiler) {
+    let mut profiler = Profiler::new(vm.program());
+    loop {
+        profiler.record(&vm);
+        if vm.step().unwrap() == StepOutcome::Halted {
+            break;
+        }
+    }
+    profiler.finish(&vm);
+    (vm.program().clone(), profiler)
+}
+
+fn collapsed(program: &Program, profiler: &Profiler) -> String {
+    let mut out = Vec::new();
+    profiler.write_collapsed(program, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn nested_calls_are_collapsed_by_call_stack() {
+    let vm = VM::with_config(
+        Parser::new().parse_file(PROGRAM).unwrap(),
+        VmConfig::default(),
+    );
+    let (program, profiler) = profile(vm);
+    assert_eq!(profiler.steps(), 11);
+    assert_eq!(profiler.counts(), [1, 1, 1, 1, 1, 2, 2, 2]);
+    assert_eq!(
+        collapsed(&program, &profiler),
+        "<principal> 2\n<principal>;a 3\n<principal>;a;b 6\n"
+    );
+}
+
+#[test]
+fn blocks_start_at_labels() {
+    let vm = VM::with_config(
+        Parser::new().parse_file(PROGRAM).unwrap(),
+        VmConfig::default(),
+    );
+    let (program, profiler) = profile(vm);
+    let block = |name: &str, start, end, entries, steps| Block {
+        name: name.to_string(),
+        start,
+        end,
+        entries,
+        steps,
+    };
+    assert_eq!(
+        profiler.blocks(&program),
+        [
+            block("<inicio>", 0, 2, 1, 2),
+            block("a", 2, 5, 1, 3),
+            block("b", 5, 8, 2, 6),
+        ]
+    );
+}
+
+#[test]
+fn recursion_gets_one_line_per_depth() {
+    let path = format!(
+        "{}/examples/factorial-recursivo.vm",
+        env!("CARGO_MANIFEST_DIR")
+    );
+    let program = Parser::new()
+        .parse_file(&fs::read_to_string(path).unwrap())
+        .unwrap();
+    let mut vm = VM::with_config(program, VmConfig::default());
+    vm.set_input(Cursor::new("4\n"));
+    vm.set_output(std::io::sink());
+    let (program, profiler) = profile(vm);
+    assert_eq!(
+        collapsed(&program, &profiler),
+        "<principal> 4\n\
+         <principal>;fact 12\n\
+         <principal>;fact;fact 12\n\
+         <principal>;fact;fact;fact 12\n\
+         <principal>;fact;fact;fact;fact 7\n"
+    );
+}
@@ -0,0 +1,135 @@
+#![cfg(feature = "serde")]
+
+use serde_json::json;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use vainilla_machine::parse::Parser;
+use vainilla_machine::vm::{Snapshot, StepOutcome, VmConfig, VM};
+
+const PROGRAM: &str = "\
+LOAD_CONST 1
+LOAD_CONST 2
+NEW_ARRAY 2
+STORE_VAR lista
+LOAD_CONST 9223372036854775807
+STORE_VAR x
+LOAD_VAR lista
+LOAD_CONST 3
+ARRAY_PUSH
+PRINT
+LOAD_VAR x
+LOAD_CONST 1
+ADD
+PRINT
+";
+
+fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "vainilla-resume-{}-{:?}-{}",
+        std::process::id(),
+        std::thread::current().id(),
+        name
+    ))
+}
+
+/// Ejecuta `steps` instrucciones y devuelve el estado guardado como JSON.
+fn saved_after(steps: u64) -> serde_json::Value {
+    let program = Parser::new().parse_file(PROGRAM).unwrap();
+    let mut vm = VM::with_config(program, VmConfig::default());
+    vm.run_steps(steps).unwrap();
+    serde_json::to_value(vm.snapshot()).unwrap()
+}
+
+fn restore(json: serde_json::Value) -> Result<VM, String> {
+    let snapshot: Snapshot = serde_json::from_value(json).unwrap();
+    VM::from_snapshot(snapshot)
+}
+
+fn vainilla(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_vainilla-machine"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn resumed_execution_keeps_variables_and_heap() {
+    let mut vm = restore(saved_after(7)).unwrap();
+    vm.set_output(std::io::sink());
+    assert_eq!(vm.ip(), 7);
+    assert_eq!(vm.heap().repr(vm.lookup_var("lista").unwrap()), "[1, 2]");
+    assert_eq!(vm.run_steps(3), Ok(StepOutcome::Continue));
+    assert_eq!(vm.heap().repr(vm.lookup_var("lista").unwrap()), "[1, 2, 3]");
+}
+
+#[test]
+fn rejects_dangling_heap_references() {
+    let mut json = saved_after(7);
+    json["stack"] = json!([{ "type": "array", "value": 99 }]);
+    let error = restore(json).err().unwrap();
+    assert!(error.contains("#99"), "{}", error);
+
+    let mut json = saved_after(7);
+    json["vars"]["lista"] = json!({ "type": "map", "value": 0 });
+    let error = restore(json).err().unwrap();
+    assert!(error.contains("debía apuntar a un map"), "{}", error);
+
+    let mut json = saved_after(7);
+    json["heap"]["slots"][0]["value"] = json!([{ "type": "array", "value": 5 }]);
+    assert!(restore(json).is_err());
+
+    let mut json = saved_after(7);
+    json["heap"]["free"] = json!([0]);
+    assert!(restore(json).is_err());
+}
+
+#[test]
+fn command_line_options_override_the_saved_config() {
+    let source = temp_file("programa.vm");
+    let state = temp_file("estado.json");
+    fs::write(&source, PROGRAM).unwrap();
+    let source = source.to_str().unwrap();
+    let state = state.to_str().unwrap();
+
+    let output = vainilla(
+        &["run", source, "--max-steps", "11", "--save-on-exit", state],
+        "",
+    );
+    assert!(output.status.success());
+
+    // Con la configuración guardada la suma se desborda.
+    let output = vainilla(&["resume", state], "");
+    assert!(!output.status.success());
+
+    let output = vainilla(&["--overflow", "wrap", "resume", state], "");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.ends_with("-9223372036854775808\n"), "{}", stdout);
+
+    // El estado se guardó sin depurador, pero al continuar con --debug sí
+    // se puede retroceder.
+    let output = vainilla(&["--debug", "resume", state], "step\nback\nquit\n");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("Se deshicieron 1 paso(s)."), "{}", stdout);
+
+    let mut json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(state).unwrap()).unwrap();
+    json["stack"] = json!([{ "type": "map", "value": 42 }]);
+    fs::write(state, json.to_string()).unwrap();
+    let output = vainilla(&["resume", state], "");
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("estado inválido"), "{}", stderr);
+}
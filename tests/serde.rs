@@ -0,0 +1,90 @@
+#![cfg(feature = "serde")]
+
+use serde_json::json;
+use std::collections::HashSet;
+use std::fs;
+use vainilla_machine::parse::Parser;
+use vainilla_machine::vm::Instruction;
+
+#[test]
+fn instructions_use_the_assembler_mnemonics() {
+    let program = Parser::new()
+        .parse_file(
+            "inicio:\nLOAD_CONST 7\nLOAD_CONST 2.5\nLOAD_CONST true\nLOAD_CONST \"hola\"\n\
+             IDIV\nJMPEQ inicio\nJMPT inicio\nNEW_ARRAY 2\nSTORE_VAR x\nCALL inicio\n",
+        )
+        .unwrap();
+    let json = serde_json::to_value(&program.instructions).unwrap();
+    assert_eq!(
+        json,
+        json!([
+            { "op": "LOAD_CONST", "arg": 7 },
+            { "op": "LOAD_CONST", "arg": 2.5 },
+            { "op": "LOAD_CONST", "arg": true },
+            { "op": "LOAD_CONST", "arg": "hola" },
+            { "op": "IDIV" },
+            { "op": "JMPEQ", "arg": 0 },
+            { "op": "JMPT", "arg": 0 },
+            { "op": "NEW_ARRAY", "arg": 2 },
+            { "op": "STORE_VAR", "arg": "x" },
+            { "op": "CALL", "arg": 0 },
+        ])
+    );
+    let back: Vec<Instruction> = serde_json::from_value(json).unwrap();
+    assert_eq!(back, program.instructions);
+}
+
+#[test]
+fn integral_floats_stay_floats() {
+    let instr = Instruction::LoadConstFloat(2.0);
+    let text = serde_json::to_string(&instr).unwrap();
+    assert_eq!(text, r#"{"op":"LOAD_CONST","arg":2.0}"#);
+    assert_eq!(serde_json::from_str::<Instruction>(&text).unwrap(), instr);
+}
+
+#[test]
+fn every_instruction_without_operand_round_trips() {
+    let mut seen = HashSet::new();
+    for instr in Instruction::NO_OPERAND {
+        assert!(
+            seen.insert(instr.mnemonic()),
+            "{} repetida",
+            instr.mnemonic()
+        );
+        let json = serde_json::to_value(instr).unwrap();
+        assert_eq!(json, json!({ "op": instr.mnemonic() }));
+        assert_eq!(&serde_json::from_value::<Instruction>(json).unwrap(), instr);
+    }
+    // 64 instrucciones en total, 20 de ellas con operando.
+    assert_eq!(seen.len(), 44);
+}
+
+#[test]
+fn examples_round_trip_through_json() {
+    let dir = format!("{}/examples", env!("CARGO_MANIFEST_DIR"));
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_none_or(|ext| ext != "vm") {
+            continue;
+        }
+        let contents = fs::read_to_string(&path).unwrap();
+        let program = Parser::new().parse_file(&contents).unwrap();
+        let json = serde_json::to_string(&program.instructions).unwrap();
+        let back: Vec<Instruction> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, program.instructions, "{}", path.display());
+    }
+}
+
+#[test]
+fn rejects_malformed_instructions() {
+    for (json, message) in [
+        (json!({ "op": "NOPE" }), "instrucción desconocida: NOPE"),
+        (json!({ "op": "LOAD_CONST" }), "requiere un valor"),
+        (json!({ "op": "JMP", "arg": -1 }), "entero no negativo"),
+        (json!({ "op": "ADD", "arg": 1 }), "no lleva operando"),
+        (json!({ "op": "LOAD_CONST_INT", "arg": 1 }), "desconocida"),
+    ] {
+        let error = serde_json::from_value::<Instruction>(json).unwrap_err();
+        assert!(error.to_string().contains(message), "{}", error);
+    }
+}
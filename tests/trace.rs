@@ -0,0 +1,78 @@
+use vainilla_machine::parse::Parser;
+use vainilla_machine::trace::{label_range, TraceFormat, Tracer};
+use vainilla_machine::vm::{StepOutcome, VmConfig, VM};
+
+const PROGRAM: &str = "\
+LOAD_CONST 3
+LOAD_CONST 4
+ADD
+STORE_VAR x
+fin:
+LOAD_VAR x
+LOAD_CONST \"a\"
+SUB
+";
+
+/// Ejecuta el programa con la traza y devuelve sus líneas.
+fn trace(format: TraceFormat, from: Option<&str>) -> Vec<String> {
+    let program = Parser::new().parse_file(PROGRAM).unwrap();
+    let range = label_range(&program, from, None).unwrap();
+    let mut vm = VM::with_config(program, VmConfig::default());
+    let mut out = Vec::new();
+    let mut tracer = Tracer::new(&mut out, format);
+    tracer.set_range(range);
+    while let Ok(StepOutcome::Continue) = tracer.step(&mut vm) {}
+    String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn text_trace_has_one_line_per_instruction() {
+    assert_eq!(
+        trace(TraceFormat::Text, None),
+        [
+            "#0     línea 1    LOAD_CONST   [] -> [3]",
+            "#1     línea 2    LOAD_CONST   [3] -> [3, 4]",
+            "#2     línea 3    ADD          [3, 4] -> [7]",
+            "#3     línea 4    STORE_VAR    [7] -> []  global x = 7",
+            "#4     línea 6    LOAD_VAR     [] -> [7]",
+            "#5     línea 7    LOAD_CONST   [7] -> [7, \"a\"]",
+            "#6     línea 8    SUB          [7, \"a\"] -> []  error: tipo incorrecto: se esperaba número pero se encontró string",
+        ]
+    );
+}
+
+#[test]
+fn only_the_range_is_traced() {
+    let lines = trace(TraceFormat::Text, Some("fin"));
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("#4 "), "{}", lines[0]);
+    assert!(label_range(
+        &Parser::new().parse_file(PROGRAM).unwrap(),
+        Some("no"),
+        None
+    )
+    .is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn json_trace_has_one_object_per_line() {
+    let lines = trace(TraceFormat::Json, None);
+    let record: serde_json::Value = serde_json::from_str(&lines[3]).unwrap();
+    assert_eq!(
+        record,
+        serde_json::json!({
+            "ip": 3,
+            "line": 4,
+            "mnemonic": "STORE_VAR",
+            "stack_before": ["7"],
+            "stack_after": [],
+            "write": { "name": "x", "scope": "global", "value": "7" },
+            "error": null,
+        })
+    );
+}
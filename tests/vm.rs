@@ -0,0 +1,370 @@
+use vainilla_machine::parse::Parser;
+use vainilla_machine::vm::{StepOutcome, VmConfig, VmError, VmErrorKind, VM};
+
+fn vm(source: &str, config: VmConfig) -> VM {
+    VM::with_config(Parser::new().parse_file(source).unwrap(), config)
+}
+
+/// Ejecuta el programa hasta el final o hasta el primer error.
+fn run(source: &str) -> (VM, Result<StepOutcome, VmError>) {
+    let mut vm = vm(source, VmConfig::default());
+    let result = vm.run();
+    (vm, result)
+}
+
+/// La pila como texto, del fondo al tope.
+fn stack(vm: &VM) -> Vec<String> {
+    vm.stack().iter().map(|val| vm.heap().repr(val)).collect()
+}
+
+#[test]
+fn substr_takes_characters() {
+    let (vm, result) = run("LOAD_CONST \"añoxyz\"\nLOAD_CONST 1\nLOAD_CONST 3\nSUBSTR\n");
+    assert_eq!(result, Ok(StepOutcome::Halted));
+    assert_eq!(stack(&vm), ["\"ñox\""]);
+}
+
+#[test]
+fn substr_with_huge_length_is_out_of_bounds() {
+    let (_, result) =
+        run("LOAD_CONST \"abc\"\nLOAD_CONST 1\nLOAD_CONST 9223372036854775807\nSUBSTR\n");
+    assert_eq!(
+        result.unwrap_err().kind,
+        VmErrorKind::IndexOutOfBounds {
+            index: i64::MAX,
+            len: 3
+        }
+    );
+}
+
+/// Una instrucción que falla por sus operandos deja la pila intacta.
+fn assert_failure_keeps_stack(source: &str, expected: VmErrorKind) {
+    let mut vm = vm(source, VmConfig::default());
+    let last = vm.program().len() - 1;
+    vm.run_steps(last as u64).unwrap();
+    let before = stack(&vm);
+    let error = vm.step().unwrap_err();
+    assert_eq!(error.kind, expected);
+    assert_eq!(error.ip, last);
+    assert_eq!(stack(&vm), before);
+}
+
+#[test]
+fn failed_array_and_map_operations_keep_their_operands() {
+    let array = "LOAD_CONST 1\nLOAD_CONST 2\nNEW_ARRAY 2\n";
+    let out_of_bounds = VmErrorKind::IndexOutOfBounds { index: 5, len: 2 };
+    assert_failure_keeps_stack(
+        &format!("{}LOAD_CONST 5\nARRAY_GET\n", array),
+        out_of_bounds.clone(),
+    );
+    assert_failure_keeps_stack(
+        &format!("{}LOAD_CONST 5\nLOAD_CONST 0\nARRAY_SET\n", array),
+        out_of_bounds,
+    );
+    assert_failure_keeps_stack("NEW_ARRAY 0\nARRAY_POP\n", VmErrorKind::EmptyArray);
+    assert_failure_keeps_stack(
+        "NEW_MAP\nLOAD_CONST \"x\"\nMAP_GET\n",
+        VmErrorKind::KeyNotFound("\"x\"".to_string()),
+    );
+    let mut vm = vm(
+        "LOAD_CONST 1\nLOAD_CONST 2\nARRAY_PUSH\n",
+        VmConfig::default(),
+    );
+    vm.run().unwrap_err();
+    assert_eq!(stack(&vm), ["1", "2"]);
+}
+
+#[test]
+fn array_operations_leave_the_array_on_the_stack() {
+    let (vm, result) = run(
+        "LOAD_CONST 1\nNEW_ARRAY 1\nLOAD_CONST 0\nLOAD_CONST 7\nARRAY_SET\nLOAD_CONST 8\nARRAY_PUSH\nARRAY_POP\n",
+    );
+    assert_eq!(result, Ok(StepOutcome::Halted));
+    assert_eq!(stack(&vm), ["[7]", "8"]);
+}
+
+#[test]
+fn call_returns_to_the_next_instruction() {
+    let (vm, result) = run("LOAD_CONST 1\nCALL doble\nCALL doble\nJMP fin\n\
+         doble:\nDUP\nADD\nRET\nfin:\n");
+    assert_eq!(result, Ok(StepOutcome::Halted));
+    assert_eq!(stack(&vm), ["4"]);
+    assert!(vm.call_stack().is_empty());
+}
+
+#[test]
+fn call_depth_is_bounded() {
+    let config = VmConfig {
+        max_call_depth: 5,
+        ..VmConfig::default()
+    };
+    let mut vm = vm("sin_fin:\nCALL sin_fin\n", config);
+    let error = vm.run().unwrap_err();
+    assert_eq!(error.kind, VmErrorKind::CallStackOverflow(5));
+    assert_eq!(vm.call_stack().len(), 5);
+
+    let (_, result) = run("LOAD_CONST 1\nRET\n");
+    assert_eq!(result.unwrap_err().kind, VmErrorKind::ReturnWithoutCall);
+}
+
+#[test]
+fn each_frame_has_its_own_locals() {
+    // La subrutina lee la global `x`, escribe una local con el mismo nombre
+    // y la global queda intacta al regresar.
+    let (vm, result) = run("LOAD_CONST 1\nSTORE_VAR x\nCALL sub\nLOAD_VAR x\nJMP fin\n\
+         sub:\nLOAD_VAR x\nLOAD_CONST 10\nADD\nSTORE_VAR x\nLOAD_VAR x\nRET\nfin:\n");
+    assert_eq!(result, Ok(StepOutcome::Halted));
+    assert_eq!(stack(&vm), ["11", "1"]);
+    assert_eq!(vm.heap().repr(&vm.globals()["x"]), "1");
+}
+
+#[test]
+fn recursion_keeps_a_copy_of_each_local() {
+    // ( n -- n! ), con `n` local en cada llamada.
+    let (vm, result) = run("LOAD_CONST 5\nCALL fact\nJMP fin\n\
+         fact:\nSTORE_VAR n\nLOAD_VAR n\nLOAD_CONST 1\nSUB\nJMPGT recursion\nLOAD_CONST 1\nRET\n\
+         recursion:\nLOAD_VAR n\nLOAD_CONST 1\nSUB\nCALL fact\nLOAD_VAR n\nMUL\nRET\nfin:\n");
+    assert_eq!(result, Ok(StepOutcome::Halted));
+    assert_eq!(stack(&vm), ["120"]);
+    assert!(vm.globals().is_empty());
+}
+
+#[test]
+fn locals_are_gone_after_ret() {
+    let (vm, result) = run("CALL sub\nLOAD_VAR y\nsub:\nLOAD_CONST 2\nSTORE_VAR y\nRET\n");
+    let error = result.unwrap_err();
+    assert_eq!(error.kind, VmErrorKind::UndefinedVariable("y".to_string()));
+    assert_eq!(error.ip, 1);
+    assert!(vm.globals().is_empty());
+}
+
+#[test]
+fn comparisons_push_booleans() {
+    let (vm, result) = run("LOAD_CONST 2\nLOAD_CONST 2.0\nEQ\n\
+         LOAD_CONST 1\nLOAD_CONST 2\nNE\n\
+         LOAD_CONST \"abc\"\nLOAD_CONST \"abd\"\nLT\n\
+         LOAD_CONST 3\nLOAD_CONST 2.5\nLE\n\
+         LOAD_CONST 3\nLOAD_CONST 2.5\nGT\n\
+         LOAD_CONST 2\nLOAD_CONST 2\nGE\n");
+    assert_eq!(result, Ok(StepOutcome::Halted));
+    assert_eq!(
+        stack(&vm),
+        ["true", "true", "true", "false", "true", "true"]
+    );
+}
+
+#[test]
+fn jmpt_and_jmpf_follow_the_boolean() {
+    let (vm, result) = run(
+        "LOAD_CONST 1\nLOAD_CONST 2\nLT\nJMPT si\nLOAD_CONST \"no\"\nsi:\n\
+         LOAD_CONST true\nJMPF no\nLOAD_CONST \"si\"\nno:\n",
+    );
+    assert_eq!(result, Ok(StepOutcome::Halted));
+    assert_eq!(stack(&vm), ["\"si\""]);
+}
+
+#[test]
+fn comparisons_and_boolean_jumps_reject_other_types() {
+    let (_, result) = run("LOAD_CONST \"a\"\nLOAD_CONST 1\nLT\n");
+    assert_eq!(
+        result.unwrap_err().kind,
+        VmErrorKind::TypeMismatch {
+            expected: "número".to_string(),
+            found: "string".to_string()
+        }
+    );
+    let (_, result) = run("LOAD_CONST 1\nJMPT fin\nfin:\n");
+    assert_eq!(
+        result.unwrap_err().kind,
+        VmErrorKind::TypeMismatch {
+            expected: "bool".to_string(),
+            found: "int".to_string()
+        }
+    );
+    let config = VmConfig {
+        truthy: true,
+        ..VmConfig::default()
+    };
+    let mut vm = vm("LOAD_CONST 0\nJMPF fin\nLOAD_CONST 1\nfin:\n", config);
+    assert_eq!(vm.run(), Ok(StepOutcome::Halted));
+    assert!(vm.stack().is_empty());
+}
+
+#[test]
+fn logical_operators_on_booleans() {
+    let (machine, result) = run("LOAD_CONST true\nLOAD_CONST false\nAND\n\
+         LOAD_CONST true\nLOAD_CONST false\nOR\n\
+         LOAD_CONST true\nLOAD_CONST true\nXOR\n\
+         LOAD_CONST false\nNOT\n");
+    assert_eq!(result, Ok(StepOutcome::Halted));
+    assert_eq!(stack(&machine), ["false", "true", "false", "true"]);
+
+    let config = VmConfig {
+        truthy: true,
+        ..VmConfig::default()
+    };
+    let mut vm = vm(
+        "LOAD_CONST 2\nLOAD_CONST 0\nOR\nLOAD_CONST \"\"\nNOT\n",
+        config,
+    );
+    assert_eq!(vm.run(), Ok(StepOutcome::Halted));
+    assert_eq!(stack(&vm), ["true", "true"]);
+}
+
+#[test]
+fn logical_operators_reject_non_booleans() {
+    let found_int = VmErrorKind::TypeMismatch {
+        expected: "bool".to_string(),
+        found: "int".to_string(),
+    };
+    assert_failure_keeps_stack("LOAD_CONST 1\nLOAD_CONST true\nAND\n", found_int.clone());
+    assert_failure_keeps_stack("LOAD_CONST true\nLOAD_CONST 0\nXOR\n", found_int.clone());
+    assert_failure_keeps_stack("LOAD_CONST 1\nNOT\n", found_int.clone());
+    assert_failure_keeps_stack("LOAD_CONST 1\nJMPF fin\nfin:\n", found_int);
+}
+
+#[test]
+fn failed_string_operations_keep_their_operands() {
+    assert_failure_keeps_stack(
+        "LOAD_CONST \"a\"\nLOAD_CONST 1\nCONCAT\n",
+        VmErrorKind::TypeMismatch {
+            expected: "string".to_string(),
+            found: "int".to_string(),
+        },
+    );
+    assert_failure_keeps_stack(
+        "LOAD_CONST 1.5\nLEN\n",
+        VmErrorKind::TypeMismatch {
+            expected: "string".to_string(),
+            found: "float".to_string(),
+        },
+    );
+    assert_failure_keeps_stack(
+        "LOAD_CONST \"abc\"\nLOAD_CONST 2\nLOAD_CONST 5\nSUBSTR\n",
+        VmErrorKind::IndexOutOfBounds { index: 7, len: 3 },
+    );
+    assert_failure_keeps_stack(
+        "LOAD_CONST \"abc\"\nLOAD_CONST \"0\"\nLOAD_CONST 1\nSUBSTR\n",
+        VmErrorKind::TypeMismatch {
+            expected: "int".to_string(),
+            found: "string".to_string(),
+        },
+    );
+    let (vm, result) = run("LOAD_CONST \"ho\"\nLOAD_CONST \"la\"\nCONCAT\nDUP\nLEN\n");
+    assert_eq!(result, Ok(StepOutcome::Halted));
+    assert_eq!(stack(&vm), ["\"hola\"", "4"]);
+}
+
+#[test]
+fn arrays_hold_any_value() {
+    let (vm, result) = run(
+        "LOAD_CONST 1\nLOAD_CONST \"dos\"\nNEW_ARRAY 0\nNEW_ARRAY 3\n\
+         DUP\nARRAY_LEN\nOVER\nLOAD_CONST 1\nARRAY_GET\n",
+    );
+    assert_eq!(result, Ok(StepOutcome::Halted));
+    assert_eq!(stack(&vm), ["[1, \"dos\", []]", "3", "\"dos\""]);
+}
+
+#[test]
+fn array_indices_are_bounds_checked() {
+    let array = "LOAD_CONST 1\nLOAD_CONST 2\nNEW_ARRAY 2\n";
+    assert_failure_keeps_stack(
+        &format!("{}LOAD_CONST -1\nARRAY_GET\n", array),
+        VmErrorKind::IndexOutOfBounds { index: -1, len: 2 },
+    );
+    assert_failure_keeps_stack(
+        &format!("{}LOAD_CONST 2\nLOAD_CONST 0\nARRAY_SET\n", array),
+        VmErrorKind::IndexOutOfBounds { index: 2, len: 2 },
+    );
+    assert_failure_keeps_stack(
+        &format!("{}LOAD_CONST 0.5\nARRAY_GET\n", array),
+        VmErrorKind::TypeMismatch {
+            expected: "int".to_string(),
+            found: "float".to_string(),
+        },
+    );
+    let (_, result) = run("LOAD_CONST 1\nNEW_ARRAY 2\n");
+    assert_eq!(result.unwrap_err().kind, VmErrorKind::StackUnderflow);
+}
+
+/// Mapa `{"a": 1, 2: "b"}` en el tope de la pila.
+const MAP: &str = "NEW_MAP\nLOAD_CONST \"a\"\nLOAD_CONST 1\nMAP_SET\n\
+                   LOAD_CONST 2\nLOAD_CONST \"b\"\nMAP_SET\n";
+
+#[test]
+fn maps_compare_by_contents() {
+    // El mismo contenido insertado en otro orden.
+    let other = "NEW_MAP\nLOAD_CONST 2\nLOAD_CONST \"b\"\nMAP_SET\n\
+                 LOAD_CONST \"a\"\nLOAD_CONST 1\nMAP_SET\n";
+    let (vm, result) = run(&format!(
+        "{MAP}{other}EQ\n{MAP}NEW_MAP\nNE\n{MAP}LOAD_CONST 2\nMAP_GET\n{MAP}MAP_KEYS\n"
+    ));
+    assert_eq!(result, Ok(StepOutcome::Halted));
+    assert_eq!(stack(&vm), ["true", "true", "\"b\"", "[2, \"a\"]"]);
+}
+
+#[test]
+fn missing_keys_and_bad_key_types_are_errors() {
+    assert_failure_keeps_stack(
+        &format!("{MAP}LOAD_CONST \"2\"\nMAP_GET\n"),
+        VmErrorKind::KeyNotFound("\"2\"".to_string()),
+    );
+    assert_failure_keeps_stack(
+        &format!("{MAP}LOAD_CONST 1.5\nLOAD_CONST 0\nMAP_SET\n"),
+        VmErrorKind::TypeMismatch {
+            expected: "int o string".to_string(),
+            found: "float".to_string(),
+        },
+    );
+    let (vm, result) = run(&format!(
+        "{MAP}LOAD_CONST \"zz\"\nMAP_DEL\nLOAD_CONST \"a\"\nMAP_HAS\n"
+    ));
+    assert_eq!(result, Ok(StepOutcome::Halted));
+    assert_eq!(stack(&vm), ["true"]);
+}
+
+/// Crea 1000 arreglos; con `keep` los guarda todos en `todos`.
+fn allocation_loop(keep: bool) -> String {
+    let alloc = match keep {
+        true => "LOAD_VAR todos\nNEW_ARRAY 0\nARRAY_PUSH\nPOP\n",
+        false => "NEW_ARRAY 0\nPOP\n",
+    };
+    format!(
+        "NEW_ARRAY 0\nSTORE_VAR todos\nLOAD_CONST 0\nSTORE_VAR i\nciclo:\n{}\
+         LOAD_VAR i\nLOAD_CONST 1\nADD\nSTORE_VAR i\n\
+         LOAD_VAR i\nLOAD_CONST 1000\nSUB\nJMPLT ciclo\n",
+        alloc
+    )
+}
+
+#[test]
+fn garbage_is_collected_under_the_heap_limit() {
+    let config = VmConfig {
+        heap_limit: 10,
+        ..VmConfig::default()
+    };
+    let mut vm = vm(&allocation_loop(false), config);
+    assert_eq!(vm.run(), Ok(StepOutcome::Halted));
+    let stats = vm.heap().stats();
+    assert_eq!(stats.allocated, 1001);
+    assert!(stats.collections > 0);
+    assert!(vm.heap().len() <= 10);
+    vm.collect_garbage();
+    assert_eq!(vm.heap().len(), 1);
+}
+
+#[test]
+fn live_data_beyond_the_heap_limit_is_an_error() {
+    let config = VmConfig {
+        heap_limit: 10,
+        ..VmConfig::default()
+    };
+    let mut vm = vm(&allocation_loop(true), config);
+    assert_eq!(vm.run().unwrap_err().kind, VmErrorKind::HeapExhausted(10));
+    assert_eq!(vm.heap().len(), 10);
+    assert_eq!(
+        vm.heap().repr(vm.lookup_var("i").unwrap()),
+        "9",
+        "el décimo arreglo ya no cabe"
+    );
+}